@@ -11,3 +11,7 @@ shared-photos =
         [female] her stream
        *[other] their stream
     }.
+
+# Error kind descriptions, see apptools/src/err/l10n.rs
+err-not-found = an entity (possibly a file) could not be found
+err-timed-out = operation timed out
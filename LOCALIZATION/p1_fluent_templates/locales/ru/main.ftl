@@ -15,3 +15,7 @@ shared-photos =
         [female] своему стриму
        *[other] своему стриму
     }.
+
+# Error kind descriptions, see apptools/src/err/l10n.rs
+err-not-found = объект (возможно, файл) не найден
+err-timed-out = время ожидания операции истекло
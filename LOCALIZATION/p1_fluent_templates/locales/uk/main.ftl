@@ -15,3 +15,6 @@ shared-photos =
         [female] свого стріму
        *[other] свого стріму
     }.
+
+# Error kind descriptions, see apptools/src/err/l10n.rs; err-timed-out falls back to en-US
+err-not-found = об'єкт (можливо, файл) не знайдено
@@ -0,0 +1,217 @@
+//! Admin channel of the token checker server: text commands over a Unix socket
+//!
+//! The socket is created with `0600` permissions, and peers are authenticated by their
+//! credentials: only processes of the user that owns the socket, or root, are served.
+//! Each command is a line, each reply is a line starting with `ok` or `error`, e.g.
+//! `echo stats | socat - UNIX-CONNECT:token_checker.admin.sock`.
+//!
+//! Tokens are written as 32 hex digits; token limits are `ttl_ms` and `max_uses`, 0 or omitted - no limit:
+//!
+//! ```text
+//! issue [ttl_ms [max_uses]]            -> ok issued <token>
+//! insert <token> [ttl_ms [max_uses]]   -> ok inserted
+//! revoke <token>                       -> ok revoked
+//! ```
+
+
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use apptools::app_err;
+use apptools::err::AppResultExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio_util::codec::LinesCodec;
+
+use crate::app_err_decl::{AppErr, AppResult, ErrList};
+use crate::token_proto::{Token, parse_token_hex};
+use crate::token_store::TokenLimits;
+
+
+/// Admin socket path used if not configured
+pub const DEFAULT_ADMIN_SOCKET: &str = "token_checker.admin.sock";
+
+/// Maximum length of a command line
+const COMMAND_LEN_MAX: usize = 256;
+
+
+/// Admin command
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+
+    /// Stops the server gracefully
+    Shutdown,
+
+    /// Replies with server counters
+    Stats,
+
+    /// Reloads the token store from disk
+    Reload,
+
+    /// Issues a new random token
+    Issue { limits: TokenLimits },
+
+    /// Inserts a token
+    Insert { token: Token, limits: TokenLimits },
+
+    /// Revokes a token
+    Revoke { token: Token },
+}
+
+
+impl AdminCommand {
+
+    /// Command name, logged instead of the command, which may carry a token
+    pub fn name(&self) -> &'static str {
+        match self {
+            AdminCommand::Shutdown => "shutdown",
+            AdminCommand::Stats => "stats",
+            AdminCommand::Reload => "reload",
+            AdminCommand::Issue { .. } => "issue",
+            AdminCommand::Insert { .. } => "insert",
+            AdminCommand::Revoke { .. } => "revoke",
+        }
+    }
+}
+
+
+impl FromStr for AdminCommand {
+    type Err = AppErr;
+
+    fn from_str(s: &str) -> AppResult<AdminCommand> {
+        let mut args = s.split_whitespace();
+        let invalid = || app_err!(ErrList::InvalidInput, Some(format!("{:?}", s.trim())));
+
+        let cmd = match args.next().unwrap_or_default() {
+            "shutdown" => AdminCommand::Shutdown,
+            "stats" => AdminCommand::Stats,
+            "reload" => AdminCommand::Reload,
+            "issue" => AdminCommand::Issue { limits: parse_limits(&mut args).ok_or_else(invalid)? },
+            "insert" => {
+                let token = args.next().and_then(parse_token_hex).ok_or_else(invalid)?;
+                AdminCommand::Insert { token, limits: parse_limits(&mut args).ok_or_else(invalid)? }
+            },
+            "revoke" => AdminCommand::Revoke { token: args.next().and_then(parse_token_hex).ok_or_else(invalid)? },
+            _ => return Err(app_err!(ErrList::UnknownAdminCommand, Some(format!("{:?}", s.trim())))),
+        };
+        if args.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(cmd)
+    }
+}
+
+
+/// Parses optional `[ttl_ms [max_uses]]` arguments, 0 means no limit
+fn parse_limits<'a>(args: &mut impl Iterator<Item = &'a str>) -> Option<TokenLimits> {
+    let mut limit = || match args.next() {
+        Some(arg) => arg.parse::<u64>().ok().map(|val| if val == 0 { None } else { Some(val) }),
+        None => Some(None),
+    };
+    Some(TokenLimits { ttl_ms: limit()?, max_uses: limit()? })
+}
+
+
+/// Codec of admin connections, one command or reply per line
+pub fn admin_codec() -> LinesCodec {
+    LinesCodec::new_with_max_length(COMMAND_LEN_MAX)
+}
+
+
+/// Listener of the admin socket, removes the socket file when dropped
+#[derive(Debug)]
+pub struct AdminListener {
+    listener: UnixListener,
+    path: PathBuf,
+
+    /// Owner of the socket file, the user allowed to connect
+    owner_uid: u32,
+}
+
+
+impl AdminListener {
+
+    /// Binds the admin socket at `path`; a stale socket left by a killed server is replaced,
+    /// fails with `AlreadyExists` if another server is listening on it
+    pub fn bind<P: AsRef<Path>>(path: P) -> AppResult<AdminListener> {
+        let path = path.as_ref().to_path_buf();
+        if let Ok(meta) = fs::symlink_metadata(&path) {
+            if !meta.file_type().is_socket() {
+                return Err(app_err!(ErrList::AlreadyExists, Some(format!("{} is not a socket", path.display()))));
+            }
+            if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+                return Err(app_err!(ErrList::AlreadyExists, Some(format!("{} is in use", path.display()))));
+            }
+            fs::remove_file(&path).with_ctx(|| format!("removing stale {}", path.display()))?;
+        }
+
+        let listener = UnixListener::bind(&path).with_ctx(|| format!("binding {}", path.display()))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        let owner_uid = fs::metadata(&path)?.uid();
+        Ok(AdminListener { listener, path, owner_uid })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Accepts a connection, fails with `AdminAccessDenied` if the peer is not allowed to connect
+    pub async fn accept(&self) -> AppResult<UnixStream> {
+        let (stream, _) = self.listener.accept().await?;
+        let uid = stream.peer_cred()?.uid();
+        if uid != self.owner_uid && uid != 0 {
+            return Err(app_err!(ErrList::AdminAccessDenied, Some(format!("peer uid {}", uid))));
+        }
+        Ok(stream)
+    }
+}
+
+
+impl Drop for AdminListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::Framed;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn commands_over_socket() {
+        assert_eq!(" stats\r".parse::<AdminCommand>().unwrap(), AdminCommand::Stats);
+        assert_eq!("quit".parse::<AdminCommand>().unwrap_err().kind, ErrList::UnknownAdminCommand);
+        assert_eq!("issue 60000".parse::<AdminCommand>().unwrap(),
+                   AdminCommand::Issue { limits: TokenLimits { ttl_ms: Some(60_000), max_uses: None } });
+        assert_eq!("insert 0505050505050505050505050505050f 0 3".parse::<AdminCommand>().unwrap(),
+                   AdminCommand::Insert { token: [5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 0xF],
+                                          limits: TokenLimits { ttl_ms: None, max_uses: Some(3) } });
+        assert_eq!("revoke 05050505050505050505050505050505".parse::<AdminCommand>().unwrap(),
+                   AdminCommand::Revoke { token: [5; 16] });
+        for invalid in &["issue -1", "issue 1 2 3", "insert", "insert 0505 1", "revoke", "stats now"] {
+            assert_eq!(invalid.parse::<AdminCommand>().unwrap_err().kind, ErrList::InvalidInput, "{}", invalid);
+        }
+
+        let path = std::env::temp_dir().join(format!("admin_test_{}.sock", std::process::id()));
+        // A stale socket is replaced, a socket in use is not
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let listener = AdminListener::bind(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let mut client = Framed::new(UnixStream::connect(&path).await.unwrap(), admin_codec());
+        let mut server = Framed::new(listener.accept().await.unwrap(), admin_codec());
+        client.send("reload").await.unwrap();
+        let line = server.next().await.unwrap().unwrap();
+        assert_eq!(line.parse::<AdminCommand>().unwrap(), AdminCommand::Reload);
+        assert_eq!(AdminListener::bind(&path).unwrap_err().kind, ErrList::AlreadyExists);
+
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
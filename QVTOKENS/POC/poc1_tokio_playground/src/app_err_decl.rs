@@ -0,0 +1,39 @@
+// Declare token checker errors
+
+apptools::declare_app_errors!(ErrList, ErrListLookupTable,
+
+    // Protocol errors, their codes are sent to clients in responses
+    UnsupportedVersion, "unsupported protocol version"
+        => { category: Data, severity: Warning },
+    UnknownOpcode, "unknown request opcode"
+        => { category: Data, severity: Warning },
+    MalformedPayload, "request payload does not match its opcode"
+        => { category: Data, severity: Warning },
+    FrameTooLarge, "frame payload exceeds the maximum length"
+        => { category: Data, severity: Warning },
+
+    // Token storage errors
+    CorruptedStorage, "token storage file is corrupted"
+        => { category: Data, severity: Critical },
+
+    // Sharded server errors
+    ShardUnavailable, "shard owning the token is not running"
+        => { category: Internal, severity: Error },
+
+    // Admin channel errors
+    UnknownAdminCommand, "unknown admin command"
+        => { category: Data, severity: Warning },
+    AdminAccessDenied, "admin peer is not allowed to connect"
+        => { category: Security, severity: Warning },
+
+    // Configuration errors
+    InvalidConfig, "configuration is invalid"
+        => { category: Config, severity: Critical },
+    InvalidSeedData, "seed data file is malformed"
+        => { category: Config, severity: Critical }
+);
+
+
+// Bind generic apptools error types to this app's error kind enum
+pub type AppErr = apptools::err::AppErr<ErrList>;
+pub type AppResult<T> = apptools::err::AppResult<T, ErrList>;
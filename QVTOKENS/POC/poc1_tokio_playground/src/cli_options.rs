@@ -0,0 +1,70 @@
+// Command line options of the token checker server, they override settings of the config file
+
+
+use clap::{App, ArgMatches};
+
+use apptools::app_err;
+
+use poc1_tokio_playground::app_err_decl::{AppErr, AppResult, ErrList};
+use poc1_tokio_playground::config::{ServerConfig, ServerVariant};
+
+
+pub fn parse_cli() -> ArgMatches {
+    App::new("poc1_tokio_playground")
+        .version("v 1.0")
+        .author("Author: iotanbo <yurizappo@gmail.com>")
+        .about("Token checker server.")
+        .arg("-c, --config=[FILE] 'TOML config file, see token_checker.toml; all settings have defaults'")
+        .arg("-b, --bind=[ADDR] 'address to listen on, e.g. 0.0.0.0:9556 (default)'")
+        .arg("--variant=[VARIANT] 'server variant: single (default) - single-threaded, sharded - multi-core'")
+        .arg("-s, --shards=[INT] 'number of shards of the sharded server; 0 (default) - number of CPU cores'")
+        .arg("-l, --log-level=[SPEC] 'log specification, e.g. info (default) or debug; RUST_LOG overrides it'")
+        .arg("--seed-file=[FILE] 'tokens inserted into a new token store, one hex token per line'")
+        .arg("--store-dir=[DIR] 'directory of the token store files (default token_store)'")
+        .get_matches()
+}
+
+
+fn invalid_option(name: &str, value: &str) -> AppErr {
+    app_err!(ErrList::InvalidConfig, Some(format!("--{} {:?}", name, value)))
+}
+
+
+/// Reads the config file if specified and applies command line options over it
+pub fn load_config(matches: &ArgMatches) -> AppResult<ServerConfig> {
+    let mut config = match matches.value_of("config") {
+        Some(path) => ServerConfig::load(path)?,
+        None => ServerConfig::default(),
+    };
+
+    if let Some(b) = matches.value_of("bind") {
+        config.server.bind_addr = b.parse().map_err(|_| invalid_option("bind", b))?;
+    }
+
+    if let Some(v) = matches.value_of("variant") {
+        config.server.variant = match v {
+            "single" => ServerVariant::Single,
+            "sharded" => ServerVariant::Sharded,
+            _ => return Err(invalid_option("variant", v)),
+        };
+    }
+
+    if let Some(s) = matches.value_of("shards") {
+        config.server.shards = s.parse().map_err(|_| invalid_option("shards", s))?;
+    }
+
+    if let Some(l) = matches.value_of("log-level") {
+        config.server.log_level = l.to_string();
+    }
+
+    if let Some(f) = matches.value_of("seed-file") {
+        config.token_store.seed_file = Some(f.into());
+    }
+
+    if let Some(d) = matches.value_of("store-dir") {
+        config.token_store.dir = d.into();
+    }
+
+    config.validate()?;
+    Ok(config)
+}
@@ -0,0 +1,304 @@
+//! Token checker server configuration: TOML config file and seed data file
+//!
+//! Every setting has a default, so a config file sets only the ones that differ, e.g.
+//!
+//! ```toml
+//! [server]
+//! variant = "sharded"
+//! shards = 4
+//! bind_addr = "0.0.0.0:9556"
+//!
+//! [token_store]
+//! seed_file = "seed_tokens.txt"
+//! ```
+//!
+//! See `token_checker.toml` for all settings.
+
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use apptools::app_err;
+use apptools::err::AppResultExt;
+
+use crate::admin::DEFAULT_ADMIN_SOCKET;
+use crate::app_err_decl::{AppErr, AppResult, ErrList};
+use crate::token_proto::{Token, parse_token_hex};
+
+
+/// Server variant
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerVariant {
+
+    /// All connections are served by a single thread
+    Single,
+
+    /// Tokens are split between shards, each running on its own thread
+    Sharded,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    pub variant: ServerVariant,
+
+    /// Number of shards of the sharded server, 0 - number of CPU cores
+    pub shards: usize,
+
+    pub bind_addr: SocketAddr,
+    pub admin_socket: PathBuf,
+
+    /// `flexi_logger` log specification, e.g. `info` or `warn, poc1_tokio_playground=debug`;
+    /// the `RUST_LOG` environment variable overrides it
+    pub log_level: String,
+}
+
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        ServerSection {
+            variant: ServerVariant::Single,
+            shards: 0,
+            bind_addr: SocketAddr::from(([0, 0, 0, 0], 9556)),
+            admin_socket: PathBuf::from(DEFAULT_ADMIN_SOCKET),
+            log_level: "info".to_string(),
+        }
+    }
+}
+
+
+/// Limits of a single-threaded server or of each shard
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+
+    /// Accepting pauses while this number of connections is open
+    pub active_conns_max: i64,
+
+    /// Connection is closed after answering this number of requests
+    pub conn_requests_max: u64,
+}
+
+
+impl Default for LimitsSection {
+    fn default() -> Self {
+        LimitsSection { active_conns_max: 12000, conn_requests_max: 1_000_000 }
+    }
+}
+
+
+/// Timeouts in millis
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsSection {
+
+    /// Connection is closed if no request is received within this time
+    pub conn_idle_ms: u64,
+
+    /// Admin connection is closed if no command is received within this time
+    pub admin_idle_ms: u64,
+
+    /// Connections still open this time after shutdown was requested are force-closed
+    pub shutdown_drain_ms: u64,
+}
+
+
+impl Default for TimeoutsSection {
+    fn default() -> Self {
+        TimeoutsSection { conn_idle_ms: 30_000, admin_idle_ms: 60_000, shutdown_drain_ms: 2000 }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenStoreSection {
+
+    /// Directory of the token store files, the sharded server uses a subdirectory per shard
+    pub dir: PathBuf,
+
+    /// Tokens inserted into a new token store, see `read_seed_file()`
+    pub seed_file: Option<PathBuf>,
+
+    /// Number of generated tokens inserted into a new token store for benchmarking:
+    /// the little-endian bytes of numbers `0..dummy_tokens`
+    pub dummy_tokens: u64,
+}
+
+
+impl Default for TokenStoreSection {
+    fn default() -> Self {
+        TokenStoreSection { dir: PathBuf::from("token_store"), seed_file: None, dummy_tokens: 1_000_000 }
+    }
+}
+
+
+/// Server configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub server: ServerSection,
+    pub limits: LimitsSection,
+    pub timeouts: TimeoutsSection,
+    pub token_store: TokenStoreSection,
+}
+
+
+impl ServerConfig {
+
+    /// Reads and validates a config file
+    pub fn load<P: AsRef<Path>>(path: P) -> AppResult<ServerConfig> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).with_ctx(|| format!("reading config {}", path.display()))?;
+        ServerConfig::parse(&text).map_err(|e| in_file(e, "config", path))
+    }
+
+    /// Parses and validates a config
+    pub fn parse(text: &str) -> AppResult<ServerConfig> {
+        let config: ServerConfig = toml::from_str(text)
+            .map_err(|e| app_err!(ErrList::InvalidConfig, Some(e.to_string())))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks values that can't be checked by their types
+    pub fn validate(&self) -> AppResult<()> {
+        let invalid = |msg: &str| Err(app_err!(ErrList::InvalidConfig, Some(msg.to_string())));
+        if self.limits.active_conns_max <= 0 {
+            return invalid("limits.active_conns_max must be positive");
+        }
+        if self.limits.conn_requests_max == 0 {
+            return invalid("limits.conn_requests_max must be positive");
+        }
+        if self.timeouts.conn_idle_ms == 0 || self.timeouts.admin_idle_ms == 0 {
+            return invalid("idle timeouts must be positive");
+        }
+        if flexi_logger::LogSpecification::parse(&self.server.log_level).is_err() {
+            return invalid(&format!("server.log_level {:?} is not a log specification", self.server.log_level));
+        }
+        Ok(())
+    }
+
+    /// Number of shards to run, `server.shards` or the number of CPU cores
+    pub fn shards(&self) -> usize {
+        match self.server.shards {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            shards => shards,
+        }
+    }
+}
+
+
+/// Token of a seed data file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SeedToken {
+
+    /// Line number in the seed data file, starting from 1
+    pub line: usize,
+
+    pub token: Token,
+    pub access_count: u64,
+}
+
+
+/// Reads a seed data file: one token per line, as hex digits, optionally followed by
+/// its access counter; empty lines and lines starting with `#` are skipped.
+///
+/// Fails with `InvalidSeedData` if a line is malformed or repeats a token.
+pub fn read_seed_file<P: AsRef<Path>>(path: P) -> AppResult<Vec<SeedToken>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).with_ctx(|| format!("reading seed file {}", path.display()))?;
+    parse_seed_data(&text).map_err(|e| in_file(e, "seed file", path))
+}
+
+
+/// Adds the file name to the message of a file content error
+fn in_file(mut e: AppErr, what: &str, path: &Path) -> AppErr {
+    e.msg = Some(format!("{} {}: {}", what, path.display(), e.msg.unwrap_or_default()));
+    e
+}
+
+
+fn parse_seed_data(text: &str) -> AppResult<Vec<SeedToken>> {
+    let mut tokens = Vec::new();
+    let mut token_lines = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line_num = i + 1;
+        let malformed = || app_err!(ErrList::InvalidSeedData, Some(format!("line {}: {:?}", line_num, line)));
+
+        let mut fields = line.split_whitespace();
+        let token = fields.next().and_then(parse_token_hex).ok_or_else(malformed)?;
+        let access_count = match fields.next() {
+            Some(count) => count.parse().map_err(|_| malformed())?,
+            None => 0,
+        };
+        if fields.next().is_some() {
+            return Err(malformed());
+        }
+        if let Some(first) = token_lines.insert(token, line_num) {
+            return Err(app_err!(ErrList::InvalidSeedData,
+                                Some(format!("line {}: token repeated, first seen on line {}", line_num, first))));
+        }
+        tokens.push(SeedToken { line: line_num, token, access_count });
+    }
+    Ok(tokens)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::token_proto::TOKEN_LEN;
+
+    use super::*;
+
+    #[test]
+    fn config_defaults_and_errors() {
+        let config = ServerConfig::parse("[server]\nvariant = \"sharded\"\nshards = 3\n\n[limits]\nactive_conns_max = 10\n")
+            .unwrap();
+        assert_eq!(config.server.variant, ServerVariant::Sharded);
+        assert_eq!(config.shards(), 3);
+        assert_eq!(config.limits.active_conns_max, 10);
+        assert_eq!(config.timeouts, TimeoutsSection::default());
+        assert_eq!(ServerConfig::parse("").unwrap(), ServerConfig::default());
+
+        for invalid in &["[server]\nvariant = \"threaded\"", "[limits]\nactive_conns = 1", "[limits]\nactive_conns_max = 0",
+                         "[server]\nbind_addr = \"localhost\"", "[server]\nlog_level = \"info, x=loud\""] {
+            assert_eq!(ServerConfig::parse(invalid).unwrap_err().kind, ErrList::InvalidConfig, "{}", invalid);
+        }
+        assert_eq!(ServerConfig::load("no_such_config.toml").unwrap_err().kind, ErrList::NotFound);
+    }
+
+    #[test]
+    fn seed_data() {
+        let tokens = parse_seed_data("# test tokens\n000102030405060708090a0b0c0d0e01\n\n  ffffffffffffffffffffffffffffff00 100\n")
+            .unwrap();
+        let mut last = [0xFF; TOKEN_LEN];
+        last[15] = 0;
+        assert_eq!(tokens, vec![SeedToken { line: 2, token: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 1], access_count: 0 },
+                                SeedToken { line: 4, token: last, access_count: 100 }]);
+
+        for invalid in &["0001", "000102030405060708090a0b0c0d0e0g", "000102030405060708090a0b0c0d0e01 x",
+                         "000102030405060708090a0b0c0d0e01 1 2"] {
+            assert_eq!(parse_seed_data(invalid).unwrap_err().kind, ErrList::InvalidSeedData, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn duplicate_seed_line() {
+        let e = parse_seed_data("000102030405060708090a0b0c0d0e01\n000102030405060708090a0b0c0d0e02\n\
+                                 000102030405060708090a0b0c0d0e01 5\n").unwrap_err();
+        assert_eq!(e.kind, ErrList::InvalidSeedData);
+        assert_eq!(e.msg.as_deref(), Some("line 3: token repeated, first seen on line 1"));
+    }
+}
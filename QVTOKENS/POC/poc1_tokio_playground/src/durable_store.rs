@@ -0,0 +1,618 @@
+//! Durable token store: periodic snapshots plus a write-ahead log of token store changes
+//!
+//! Files of the store directory, all integers are big-endian:
+//!
+//! * `tokens.snap` - snapshot: magic, generation: u64, tokens count: u64, tokens,
+//!   crc32 of all previous bytes: u32
+//! * `tokens.wal` - log of changes made after the snapshot: magic, generation: u64, records;
+//!   each record is `payload length: u32, crc32 of payload: u32, payload`
+//!
+//! Both files are replaced atomically, by writing and renaming a temporary file. A snapshot
+//! increments the generation and starts a new log; a log of an older generation is contained
+//! in the snapshot and is ignored on recovery. Recovery replays the log over the snapshot
+//! up to the first incomplete or corrupted record, left by a crash in the middle of a write,
+//! and truncates the log there.
+//!
+//! Changes are buffered in memory until `sync()`, a crash loses changes made after the last sync.
+//! A failed log write is cut off the log, so the records synced after it are not hidden from
+//! recovery; if the log can't be cut or created, the next sync writes a snapshot instead.
+
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use bytes::{Buf, BufMut};
+
+use apptools::app_err;
+use apptools::err::AppResultExt;
+
+use crate::app_err_decl::{AppResult, ErrList};
+use crate::token_proto::{Token, TokenStatus, TOKEN_LEN};
+use crate::token_store::{MemTokenStore, TokenEntry, TokenLimits, TokenStore};
+
+
+/// Snapshot file name
+pub const SNAPSHOT_FILE: &str = "tokens.snap";
+
+/// Write-ahead log file name
+pub const WAL_FILE: &str = "tokens.wal";
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"QVTSNAP1";
+const WAL_MAGIC: &[u8; 8] = b"QVTWAL01";
+
+/// Length of magic and generation
+const FILE_HEADER_LEN: usize = 16;
+
+/// Length of an encoded `TokenEntry`
+const ENTRY_LEN: usize = 25;
+
+/// Length of a log record header
+const RECORD_HEADER_LEN: usize = 8;
+
+
+fn put_entry(dst: &mut Vec<u8>, token: &Token, entry: &TokenEntry) {
+    dst.put_slice(token);
+    dst.put_u64(entry.access_count);
+    dst.put_u64(entry.expires_at.unwrap_or(0));
+    dst.put_u64(entry.max_uses.unwrap_or(0));
+    dst.put_u8(entry.revoked as u8);
+}
+
+
+/// Reads an entry written by `put_entry`, `src` must hold at least `TOKEN_LEN + ENTRY_LEN` bytes
+fn get_entry(src: &mut &[u8]) -> (Token, TokenEntry) {
+    let mut token = [0_u8; TOKEN_LEN];
+    src.copy_to_slice(&mut token);
+    let non_zero = |val| if val == 0 { None } else { Some(val) };
+    let entry = TokenEntry {
+        access_count: src.get_u64(),
+        expires_at: non_zero(src.get_u64()),
+        max_uses: non_zero(src.get_u64()),
+        revoked: src.get_u8() != 0,
+    };
+    (token, entry)
+}
+
+
+/// Logged token store change; replaying changes over the same state gives the same result
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Record {
+    Insert(Token, TokenEntry),
+    Revoke(Token),
+
+    /// Check that changed the token: incremented its counter or removed it as expired
+    Check(Token, u64),
+
+    /// Sweep that removed tokens: time and maximum number of tokens
+    Sweep(u64, u64),
+}
+
+
+impl Record {
+
+    const INSERT: u8 = 1;
+    const REVOKE: u8 = 2;
+    const CHECK: u8 = 3;
+    const SWEEP: u8 = 4;
+
+    fn put(&self, dst: &mut Vec<u8>) {
+        let mut payload = Vec::with_capacity(1 + TOKEN_LEN + ENTRY_LEN);
+        match self {
+            Record::Insert(token, entry) => {
+                payload.put_u8(Record::INSERT);
+                put_entry(&mut payload, token, entry);
+            },
+            Record::Revoke(token) => {
+                payload.put_u8(Record::REVOKE);
+                payload.put_slice(token);
+            },
+            Record::Check(token, now) => {
+                payload.put_u8(Record::CHECK);
+                payload.put_slice(token);
+                payload.put_u64(*now);
+            },
+            Record::Sweep(now, max) => {
+                payload.put_u8(Record::SWEEP);
+                payload.put_u64(*now);
+                payload.put_u64(*max);
+            },
+        }
+        dst.put_u32(payload.len() as u32);
+        dst.put_u32(crc32fast::hash(&payload));
+        dst.put_slice(&payload);
+    }
+
+    /// Parses the next record of `src`; returns the record and its length
+    /// or `None` if the record is incomplete or corrupted.
+    fn parse(mut src: &[u8]) -> Option<(Record, usize)> {
+        if src.len() < RECORD_HEADER_LEN {
+            return None;
+        }
+        let len = src.get_u32() as usize;
+        let crc = src.get_u32();
+        if src.len() < len || crc32fast::hash(&src[..len]) != crc {
+            return None;
+        }
+
+        let mut payload = &src[..len];
+        let get_token = |payload: &mut &[u8]| {
+            let mut token = [0_u8; TOKEN_LEN];
+            payload.copy_to_slice(&mut token);
+            token
+        };
+        let record = match (payload.get_u8(), len - 1) {
+            (Record::INSERT, l) if l == TOKEN_LEN + ENTRY_LEN => {
+                let (token, entry) = get_entry(&mut payload);
+                Record::Insert(token, entry)
+            },
+            (Record::REVOKE, l) if l == TOKEN_LEN => Record::Revoke(get_token(&mut payload)),
+            (Record::CHECK, l) if l == TOKEN_LEN + 8 => {
+                let token = get_token(&mut payload);
+                Record::Check(token, payload.get_u64())
+            },
+            (Record::SWEEP, 16) => Record::Sweep(payload.get_u64(), payload.get_u64()),
+            _ => return None,
+        };
+        Some((record, RECORD_HEADER_LEN + len))
+    }
+
+    fn apply(self, mem: &mut MemTokenStore) -> AppResult<()> {
+        match self {
+            Record::Insert(token, entry) => mem.insert_entry(token, entry)
+                .map_err(|e| app_err!(ErrList::CorruptedStorage, Some(format!("replaying {}: {}", WAL_FILE, e))))?,
+            Record::Revoke(token) => mem.revoke(&token)
+                .map_err(|e| app_err!(ErrList::CorruptedStorage, Some(format!("replaying {}: {}", WAL_FILE, e))))?,
+            Record::Check(token, now) => {
+                mem.check(&token, now);
+            },
+            Record::Sweep(now, max) => {
+                mem.sweep(now, max as usize);
+            },
+        }
+        Ok(())
+    }
+}
+
+
+/// What was recovered when the store was opened
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+
+    /// Generation of the snapshot, 0 if there was no snapshot
+    pub generation: u64,
+
+    /// Number of tokens loaded from the snapshot
+    pub snapshot_tokens: usize,
+
+    /// Number of log records replayed over the snapshot
+    pub replayed_records: u64,
+
+    /// Length of the incomplete or corrupted log tail that was discarded
+    pub discarded_bytes: u64,
+}
+
+
+/// Token store persisted to a directory
+#[derive(Debug)]
+pub struct DurableTokenStore {
+    mem: MemTokenStore,
+    dir: PathBuf,
+    generation: u64,
+
+    /// Log of the current generation, `None` if it couldn't be created or a failed write couldn't
+    /// be cut off; the next sync writes a snapshot then
+    wal: Option<File>,
+
+    /// Length of the log up to the last synced record
+    wal_len: u64,
+
+    /// Records not written to the log yet
+    pending: Vec<u8>,
+
+    recovery: RecoveryReport,
+}
+
+
+impl DurableTokenStore {
+
+    /// Opens the store in `dir`, creating the directory if required, and recovers its tokens
+    pub fn open<P: AsRef<Path>>(dir: P) -> AppResult<DurableTokenStore> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).with_ctx(|| format!("creating {}", dir.display()))?;
+
+        let (generation, mut mem) = match read_if_exists(&dir.join(SNAPSHOT_FILE))? {
+            Some(data) => load_snapshot(&data)?,
+            None => (0, MemTokenStore::new()),
+        };
+        let mut recovery = RecoveryReport { generation, snapshot_tokens: mem.len(), ..RecoveryReport::default() };
+
+        // A log of an older generation is contained in the snapshot
+        let wal_path = dir.join(WAL_FILE);
+        let wal_data = read_if_exists(&wal_path)?.unwrap_or_default();
+        let mut valid_len = 0;
+        if wal_data.len() >= FILE_HEADER_LEN && &wal_data[..8] == WAL_MAGIC
+            && (&wal_data[8..FILE_HEADER_LEN]).get_u64() == generation {
+            valid_len = FILE_HEADER_LEN;
+            while let Some((record, len)) = Record::parse(&wal_data[valid_len..]) {
+                record.apply(&mut mem)?;
+                valid_len += len;
+                recovery.replayed_records += 1;
+            }
+        }
+        recovery.discarded_bytes = (wal_data.len() - valid_len) as u64;
+
+        let (wal, wal_len) = if valid_len == 0 {
+            (create_wal(&dir, generation)?, FILE_HEADER_LEN)
+        } else {
+            let wal = OpenOptions::new().append(true).open(&wal_path)
+                .with_ctx(|| format!("opening {}", wal_path.display()))?;
+            wal.set_len(valid_len as u64)?;
+            wal.sync_all()?;
+            (wal, valid_len)
+        };
+
+        Ok(DurableTokenStore { mem, dir, generation, wal: Some(wal), wal_len: wal_len as u64, pending: Vec::new(),
+                               recovery })
+    }
+
+    /// What was recovered when the store was opened
+    pub fn recovery(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// Generation of the latest snapshot
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Drops tokens in memory and recovers them from the store files again, e.g. after
+    /// the files were restored from a backup; changes that are not synced yet are lost.
+    ///
+    /// The store is left unchanged if the files can't be read.
+    pub fn reload(&mut self) -> AppResult<()> {
+        *self = DurableTokenStore::open(&self.dir)?;
+        Ok(())
+    }
+
+    /// Writes buffered changes to the log and flushes it to disk, or writes a snapshot if the log
+    /// is unusable; failed writes are retried by the next sync
+    pub fn sync(&mut self) -> AppResult<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let wal = match &mut self.wal {
+            Some(wal) => wal,
+            None => return self.snapshot(),
+        };
+        if let Err(e) = wal.write_all(&self.pending).and_then(|_| wal.sync_data()) {
+            // Recovery stops at a torn record, records appended after it would be lost
+            if wal.set_len(self.wal_len).is_err() {
+                self.wal = None;
+            }
+            return Err(e).ctx(format!("writing {}", WAL_FILE));
+        }
+        self.wal_len += self.pending.len() as u64;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Writes a snapshot of all tokens and starts a new log;
+    ///
+    /// Blocks until done, the time is proportional to the number of tokens.
+    pub fn snapshot(&mut self) -> AppResult<()> {
+        let generation = self.generation + 1;
+        let mut data = Vec::with_capacity(FILE_HEADER_LEN + 12 + self.mem.len() * (TOKEN_LEN + ENTRY_LEN));
+        data.put_slice(SNAPSHOT_MAGIC);
+        data.put_u64(generation);
+        data.put_u64(self.mem.len() as u64);
+        for (token, entry) in self.mem.iter() {
+            put_entry(&mut data, token, entry);
+        }
+        let crc = crc32fast::hash(&data);
+        data.put_u32(crc);
+
+        write_atomically(&self.dir, SNAPSHOT_FILE, &data)?;
+        // Buffered changes are contained in the snapshot, the log of the previous generation is
+        // ignored from now on
+        self.pending.clear();
+        self.generation = generation;
+        self.wal = None;
+        self.wal = Some(create_wal(&self.dir, generation)?);
+        self.wal_len = FILE_HEADER_LEN as u64;
+        Ok(())
+    }
+
+    fn log(&mut self, record: Record) {
+        record.put(&mut self.pending);
+    }
+}
+
+
+impl TokenStore for DurableTokenStore {
+
+    fn insert(&mut self, token: Token, limits: TokenLimits, now: u64) -> AppResult<()> {
+        self.insert_entry(token, TokenEntry::new(limits, now))
+    }
+
+    fn insert_entry(&mut self, token: Token, entry: TokenEntry) -> AppResult<()> {
+        self.mem.insert_entry(token, entry)?;
+        self.log(Record::Insert(token, entry));
+        Ok(())
+    }
+
+    fn issue_where(&mut self, limits: TokenLimits, now: u64, accept: &dyn Fn(&Token) -> bool) -> AppResult<Token> {
+        let token = self.mem.issue_where(limits, now, accept)?;
+        self.log(Record::Insert(token, TokenEntry::new(limits, now)));
+        Ok(token)
+    }
+
+    fn revoke(&mut self, token: &Token) -> AppResult<()> {
+        self.mem.revoke(token)?;
+        self.log(Record::Revoke(*token));
+        Ok(())
+    }
+
+    fn check(&mut self, token: &Token, now: u64) -> (TokenStatus, u64) {
+        let (status, access_count) = self.mem.check(token, now);
+        if let TokenStatus::Valid | TokenStatus::Expired = status {
+            self.log(Record::Check(*token, now));
+        }
+        (status, access_count)
+    }
+
+    fn sweep(&mut self, now: u64, max: usize) -> usize {
+        let removed = self.mem.sweep(now, max);
+        if removed > 0 {
+            self.log(Record::Sweep(now, max as u64));
+        }
+        removed
+    }
+
+    fn len(&self) -> usize {
+        self.mem.len()
+    }
+}
+
+
+fn read_if_exists(path: &Path) -> AppResult<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_ctx(|| format!("reading {}", path.display())),
+    }
+}
+
+
+fn load_snapshot(data: &[u8]) -> AppResult<(u64, MemTokenStore)> {
+    let corrupted = |what: &str| app_err!(ErrList::CorruptedStorage, Some(format!("{}: {}", SNAPSHOT_FILE, what)));
+
+    if data.len() < FILE_HEADER_LEN + 12 || &data[..8] != SNAPSHOT_MAGIC {
+        return Err(corrupted("bad header"));
+    }
+    let (body, mut crc) = data.split_at(data.len() - 4);
+    if crc32fast::hash(body) != crc.get_u32() {
+        return Err(corrupted("checksum mismatch"));
+    }
+
+    let mut raw = &body[8..];
+    let generation = raw.get_u64();
+    let count = raw.get_u64() as usize;
+    if raw.len() != count * (TOKEN_LEN + ENTRY_LEN) {
+        return Err(corrupted("tokens count mismatch"));
+    }
+    let mut mem = MemTokenStore::new();
+    while raw.has_remaining() {
+        let (token, entry) = get_entry(&mut raw);
+        mem.insert_entry(token, entry).map_err(|_| corrupted("duplicate token"))?;
+    }
+    Ok((generation, mem))
+}
+
+
+/// Replaces `dir/name` with `data`, so a crash leaves either the old or the new file
+fn write_atomically(dir: &Path, name: &str, data: &[u8]) -> AppResult<()> {
+    let path = dir.join(name);
+    let tmp_path = dir.join(format!("{}.tmp", name));
+    let mut tmp = File::create(&tmp_path).with_ctx(|| format!("creating {}", tmp_path.display()))?;
+    tmp.write_all(data).with_ctx(|| format!("writing {}", tmp_path.display()))?;
+    tmp.sync_all()?;
+    fs::rename(&tmp_path, &path).with_ctx(|| format!("renaming {}", tmp_path.display()))?;
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+
+/// Replaces the log with an empty log of the given generation, returns it opened for appending
+fn create_wal(dir: &Path, generation: u64) -> AppResult<File> {
+    let mut header = Vec::with_capacity(FILE_HEADER_LEN);
+    header.put_slice(WAL_MAGIC);
+    header.put_u64(generation);
+    write_atomically(dir, WAL_FILE, &header)?;
+    let path = dir.join(WAL_FILE);
+    OpenOptions::new().append(true).open(&path).with_ctx(|| format!("opening {}", path.display()))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// Set in the environment of the writer process killed by `killed_writer_recovers`
+    const CRASH_DIR_ENV: &str = "DURABLE_STORE_CRASH_DIR";
+
+    const TOKEN: Token = [7; TOKEN_LEN];
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("durable_store_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Access counter of a valid token, checking it doesn't change the counter of the store
+    fn access_count(store: &DurableTokenStore, token: &Token) -> Option<u64> {
+        store.mem.iter().find(|(t, _)| *t == token).map(|(_, entry)| entry.access_count)
+    }
+
+    #[test]
+    fn replays_log_over_snapshot() {
+        let dir = test_dir("replay");
+        let mut store = DurableTokenStore::open(&dir).unwrap();
+        store.insert(TOKEN, TokenLimits::default(), 0).unwrap();
+        store.insert([1; TOKEN_LEN], TokenLimits { ttl_ms: Some(100), max_uses: None }, 0).unwrap();
+        store.check(&TOKEN, 0);
+        store.sync().unwrap();
+        let old_wal = fs::read(dir.join(WAL_FILE)).unwrap();
+
+        store.snapshot().unwrap();
+        store.check(&TOKEN, 0);
+        let issued = store.issue(TokenLimits::default(), 0).unwrap();
+        assert_eq!(store.sweep(100, 10), 1);
+        store.revoke(&issued).unwrap();
+        store.sync().unwrap();
+        store.check(&TOKEN, 0);  // not synced, lost
+        drop(store);
+
+        let mut store = DurableTokenStore::open(&dir).unwrap();
+        assert_eq!(*store.recovery(), RecoveryReport { generation: 1, snapshot_tokens: 2, replayed_records: 4,
+                                                       discarded_bytes: 0 });
+        assert_eq!(access_count(&store, &TOKEN), Some(2));
+        assert_eq!(store.check(&issued, 0), (TokenStatus::Revoked, 0));
+        assert_eq!(store.len(), 2);
+
+        // Crash after the snapshot is written, before the log is replaced: the old log is ignored
+        store.snapshot().unwrap();
+        drop(store);
+        fs::write(dir.join(WAL_FILE), &old_wal).unwrap();
+        let mut store = DurableTokenStore::open(&dir).unwrap();
+        assert_eq!((store.recovery().replayed_records, store.generation()), (0, 2));
+        assert_eq!(access_count(&store, &TOKEN), Some(2));
+
+        // Reloading drops changes that are not synced
+        store.check(&TOKEN, 0);
+        store.reload().unwrap();
+        assert_eq!(access_count(&store, &TOKEN), Some(2));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn torn_log_tail_is_discarded() {
+        let dir = test_dir("torn");
+        let mut store = DurableTokenStore::open(&dir).unwrap();
+        store.insert(TOKEN, TokenLimits::default(), 0).unwrap();
+        for now in 0..5 {
+            store.check(&TOKEN, now);
+        }
+        store.sync().unwrap();
+        drop(store);
+        let wal = fs::read(dir.join(WAL_FILE)).unwrap();
+
+        // Cut the log at every byte, as a crash in the middle of a write would do
+        let mut record_ends = vec![FILE_HEADER_LEN];
+        while let Some((_, len)) = Record::parse(&wal[*record_ends.last().unwrap()..]) {
+            record_ends.push(record_ends.last().unwrap() + len);
+        }
+        assert_eq!(record_ends.len(), 7);
+        for cut in FILE_HEADER_LEN..=wal.len() {
+            fs::write(dir.join(WAL_FILE), &wal[..cut]).unwrap();
+            let store = DurableTokenStore::open(&dir).unwrap();
+            let records = record_ends.iter().filter(|end| **end <= cut).count() - 1;
+            assert_eq!(store.recovery().replayed_records, records as u64);
+            assert_eq!(store.recovery().discarded_bytes, (cut - record_ends[records]) as u64);
+            assert_eq!(access_count(&store, &TOKEN), records.checked_sub(1).map(|checks| checks as u64));
+        }
+
+        // A corrupted record ends the log
+        let mut corrupted = wal.clone();
+        corrupted[record_ends[3] + RECORD_HEADER_LEN + 2] ^= 0x01;
+        fs::write(dir.join(WAL_FILE), &corrupted).unwrap();
+        let store = DurableTokenStore::open(&dir).unwrap();
+        assert_eq!(access_count(&store, &TOKEN), Some(2));
+        assert_eq!(fs::metadata(dir.join(WAL_FILE)).unwrap().len(), record_ends[3] as u64);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_log_write_falls_back_to_snapshot() {
+        let dir = test_dir("failed_write");
+        let mut store = DurableTokenStore::open(&dir).unwrap();
+        store.insert(TOKEN, TokenLimits::default(), 0).unwrap();
+        store.sync().unwrap();
+
+        // A write failed after writing part of a record, and the log can't be cut
+        OpenOptions::new().append(true).open(dir.join(WAL_FILE)).unwrap().write_all(&[0xFF; 5]).unwrap();
+        store.wal = Some(File::open(dir.join(WAL_FILE)).unwrap());
+        store.check(&TOKEN, 0);
+        assert!(store.sync().is_err());
+        assert!(store.wal.is_none());
+
+        // The next sync writes a snapshot with the failed changes, later ones are logged again
+        store.check(&TOKEN, 0);
+        store.sync().unwrap();
+        assert_eq!(store.generation(), 1);
+        store.check(&TOKEN, 0);
+        store.sync().unwrap();
+        drop(store);
+
+        let store = DurableTokenStore::open(&dir).unwrap();
+        assert_eq!(*store.recovery(), RecoveryReport { generation: 1, snapshot_tokens: 1, replayed_records: 1,
+                                                       discarded_bytes: 0 });
+        assert_eq!(access_count(&store, &TOKEN), Some(3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Runs a writer process that syncs batches of checks and reports synced counters,
+    /// kills it and verifies that every reported check is recovered
+    #[test]
+    fn killed_writer_recovers() {
+        if let Ok(dir) = std::env::var(CRASH_DIR_ENV) {
+            let mut store = DurableTokenStore::open(&dir).unwrap();
+            let _ = store.insert(TOKEN, TokenLimits::default(), 0);
+            let started = Instant::now();
+            while started.elapsed() < Duration::from_secs(20) {
+                let mut access_count = 0;
+                for _ in 0..10 {
+                    access_count = store.check(&TOKEN, 0).1;
+                }
+                store.sync().unwrap();
+                println!("synced {}", access_count);
+            }
+            return;
+        }
+
+        let dir = test_dir("killed");
+        let mut last_synced = 0;
+        for round in 0..3 {
+            let mut child = Command::new(std::env::current_exe().unwrap())
+                .args(["durable_store::tests::killed_writer_recovers", "--exact", "--nocapture"])
+                .env(CRASH_DIR_ENV, &dir)
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let stdout = BufReader::new(child.stdout.take().unwrap());
+            let synced = stdout.lines()
+                .filter_map(|line| line.unwrap().strip_prefix("synced ").map(|cnt| cnt.parse::<u64>().unwrap()))
+                .take(100 * (round + 1))
+                .last()
+                .unwrap();
+            child.kill().unwrap();
+            child.wait().unwrap();
+            assert!(synced > last_synced);
+
+            let store = DurableTokenStore::open(&dir).unwrap();
+            let recovered = access_count(&store, &TOKEN).unwrap();
+            assert!(recovered >= synced, "round {}: recovered {} < synced {}", round, recovered, synced);
+            last_synced = recovered;
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,9 @@
+// Modules shared by the token checker server and its clients
+
+pub mod admin;
+pub mod app_err_decl;
+pub mod config;
+pub mod durable_store;
+pub mod shutdown;
+pub mod token_proto;
+pub mod token_store;
@@ -1,34 +1,46 @@
 
-
 use flexi_logger::{Logger, LogTarget, opt_format};  // detailed_format
 // detailed_format, ReconfigurationHandle
 
-// LocalSet allows to create async tasks on a single thread 
+use log::*;
+
+// LocalSet allows to create async tasks on a single thread
 use tokio::task::LocalSet;
 
+use poc1_tokio_playground::config::{ServerConfig, ServerVariant};
+
 // Import modules of this project
-//mod single_thread_http_srv_demo;
-// mod single_thread_token_checker;
+mod cli_options;
 mod token_checker_srv_for_bench;
+mod token_checker_srv_sharded;
 
 
-async fn dummy_async_app() {
-    //println!("== Single Thread Token Checker demo start ==");
-    //println!("== Single Thread Token Checker demo end ==");
-    
-    //single_thread_http_srv_demo::run_server(9555).await.unwrap();
-    //single_thread_token_checker::run_server(9556).await.unwrap();
-    token_checker_srv_for_bench::run_server(9556).await.unwrap();
-    println!("== Token Checker Server shutdown complete ==");
+/// Runs the server variant selected by the config until shutdown
+fn run_server(config: &ServerConfig) -> Result<(), Box<dyn std::error::Error>> {
+    match config.server.variant {
+        ServerVariant::Sharded => token_checker_srv_sharded::run_server(config),
+        ServerVariant::Single => {
+            // Create single-threaded runtime, enable_all() enables I/O and time drivers.
+            let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
 
-    
+            // Local set always creates tasks on a single thread
+            // and allows !Sync types to be shared between tasks
+            let local = LocalSet::new();
+
+            local.block_on(&rt, token_checker_srv_for_bench::run_server(config))
+        },
+    }
 }
 
 
 fn main() {
 
-    // Init logger first
-    let logger = Logger::with_env_or_str("info")
+    let matches = cli_options::parse_cli();
+    let config = cli_options::load_config(&matches);
+
+    // Init logger first; config errors are logged with the default log level
+    let log_spec = config.as_ref().map_or("info", |config| config.server.log_level.as_str());
+    let logger = Logger::with_env_or_str(log_spec)
        .log_target(LogTarget::StdErr)
        // .buffer_and_flush()  // This is required only for buffered file write
        //.adaptive_format_for_stderr(AdaptiveFormat::Default)
@@ -36,16 +48,26 @@ fn main() {
        .format(opt_format)
        .start().unwrap();
 
-    // Create single-threaded runtime, enable_all() enables I/O and time drivers.
-    let mut rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
-
-    // Local set always creates tasks on a single thread 
-    // and allows !Sync types to be shared between tasks
-    let local = LocalSet::new();
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            apptools::log_app_err!(e);
+            logger.shutdown();
+            std::process::exit(2);
+        },
+    };
+    debug!("* config: {:?}", config);
 
-    local.block_on(&mut rt, dummy_async_app());
+    let result = run_server(&config);
+    match &result {
+        Ok(()) => println!("== Token Checker Server shutdown complete =="),
+        Err(e) => error!("! Token Checker Server failed: {}", e),
+    }
 
     // Shutdown logger task
     logger.shutdown();
 
+    if result.is_err() {
+        std::process::exit(1);
+    }
 }
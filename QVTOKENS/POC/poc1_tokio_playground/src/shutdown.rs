@@ -0,0 +1,123 @@
+//! Server shutdown coordination: a shutdown request wakes up every task waiting for it
+
+
+use std::sync::Arc;
+
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+
+/// Why the server is shutting down
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+
+    /// Shutdown admin command received
+    AdminCommand,
+
+    /// SIGINT or SIGTERM received
+    Signal(&'static str),
+
+    /// A shard of the sharded server failed
+    ShardFailed,
+}
+
+
+/// Broadcasts a shutdown request to all subscribed tasks, on any thread
+#[derive(Debug, Clone)]
+pub struct ShutdownCoordinator {
+    tx: Arc<watch::Sender<Option<ShutdownReason>>>,
+}
+
+
+impl Default for ShutdownCoordinator {
+    fn default() -> Self {
+        ShutdownCoordinator { tx: Arc::new(watch::channel(None).0) }
+    }
+}
+
+
+impl ShutdownCoordinator {
+
+    pub fn new() -> ShutdownCoordinator {
+        ShutdownCoordinator::default()
+    }
+
+    /// Requests shutdown; returns `false` if it was requested already, the first reason is kept
+    pub fn request(&self, reason: ShutdownReason) -> bool {
+        self.tx.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(reason);
+            true
+        })
+    }
+
+    pub fn reason(&self) -> Option<ShutdownReason> {
+        *self.tx.borrow()
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.reason().is_some()
+    }
+
+    pub fn subscribe(&self) -> ShutdownSignal {
+        ShutdownSignal(self.tx.subscribe())
+    }
+}
+
+
+/// Receiving side of `ShutdownCoordinator`
+#[derive(Debug, Clone)]
+pub struct ShutdownSignal(watch::Receiver<Option<ShutdownReason>>);
+
+
+impl ShutdownSignal {
+
+    /// Waits until shutdown is requested, returns at once if it is requested already
+    pub async fn recv(&mut self) -> ShutdownReason {
+        let reason = self.0.wait_for(Option::is_some).await.map(|reason| *reason);
+        match reason {
+            Ok(reason) => reason.unwrap(),
+            // All coordinators are dropped, shutdown can't be requested anymore
+            Err(_) => std::future::pending().await,
+        }
+    }
+}
+
+
+/// Waits for SIGINT or SIGTERM
+pub async fn wait_for_signal() -> std::io::Result<ShutdownReason> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.map(|_| ShutdownReason::Signal("SIGINT")),
+        _ = sigterm.recv() => Ok(ShutdownReason::Signal("SIGTERM")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use futures::FutureExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn request_wakes_up_subscribers() {
+        let shutdown = ShutdownCoordinator::new();
+        let mut signal = shutdown.subscribe();
+        assert_eq!(signal.recv().now_or_never(), None);
+
+        let waiter = tokio::spawn(async move { signal.recv().await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(shutdown.clone().request(ShutdownReason::Signal("SIGTERM")));
+        assert!(!shutdown.request(ShutdownReason::AdminCommand));
+
+        assert_eq!(waiter.await.unwrap(), ShutdownReason::Signal("SIGTERM"));
+        assert_eq!(shutdown.subscribe().recv().await, ShutdownReason::Signal("SIGTERM"));
+        assert!(shutdown.is_requested());
+    }
+}
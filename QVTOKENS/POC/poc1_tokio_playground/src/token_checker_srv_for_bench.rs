@@ -1,32 +1,57 @@
-/// Single-threaded token checker server for benchmarking
+//! Single-threaded token checker server for benchmarking
 
 
 
-use tokio::net::TcpListener;
-use tokio::io::{AsyncReadExt};  // , AsyncWriteExt
+use tokio::net::{TcpListener, UnixStream};
+use tokio_util::codec::Framed;
+use futures::future::LocalBoxFuture;
+use futures::{FutureExt, SinkExt, StreamExt};
 use std::rc::Rc;
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use tokio::time::{self, Duration};
+use std::net::SocketAddr;
+use tokio::sync::Notify;
+use tokio::task::AbortHandle;
+use tokio::time::{self, Duration, Instant};
 // use std::net::Shutdown;
 
 
+use std::path::Path;
+use std::fmt;
 use std::time::{SystemTime};
 
 use log::*;
 
+use apptools::app_err;
 
-/// Period of time in millis to poll for shutdown
-const SHUTDOWN_POLLING_TIME: u64 = 1000;
+use poc1_tokio_playground::admin::{AdminCommand, AdminListener, admin_codec};
+use poc1_tokio_playground::config::{ServerConfig, read_seed_file};
+use poc1_tokio_playground::token_proto::{ServerCodec, Request, Response, Token, token_hex};
+use poc1_tokio_playground::token_store::{TokenStore, TokenEntry, now_millis};
+use poc1_tokio_playground::durable_store::DurableTokenStore;
+use poc1_tokio_playground::app_err_decl::{AppErr, AppResult, ErrList};
+use poc1_tokio_playground::shutdown::{ShutdownCoordinator, ShutdownReason, ShutdownSignal, wait_for_signal};
 
-/// Maximum number of active connections
-const ACTIVE_CONNS_MAX: i64 = 12000;
 
-/// Quit message that initializes server shutdown if received
-const QUIT_MSG: [u8; 16] = [0xFF_u8; 16];
+/// Period of time in millis between expired tokens sweeps
+const TOKEN_SWEEP_PERIOD: u64 = 1000;
 
+/// Maximum number of tokens removed by a sweep, limits the time other tasks wait
+const TOKEN_SWEEP_BATCH: usize = 10_000;
 
-struct GlobalState {
+/// Period of time in millis between token store log syncs; changes made after the last sync
+/// are lost on crash
+const TOKEN_STORE_SYNC_PERIOD: u64 = 100;
+
+/// Period of time in millis between token store snapshots
+const TOKEN_STORE_SNAPSHOT_PERIOD: u64 = 60_000;
+
+
+/// State of a single-threaded server, or of a shard of the sharded server
+pub(crate) struct GlobalState {
+
+    /// Limits and timeouts are read from here
+    pub config: ServerConfig,
 
     /// Id to be assigned to next accepted connection
     next_conn_id: Cell<i64>,
@@ -40,75 +65,225 @@ struct GlobalState {
     /// Total of requests processed from server start
     requests_cnt: Cell<i64>,
 
-    /// True if the server is shutting down;
-    /// Each task should periodically poll this value
-    /// and gracefully exit if it is `true`.
-    #[allow(unused)]
-    is_shutting_down: Cell<bool>,
+    /// Connections being served, drained on shutdown
+    active_conns: RefCell<HashMap<i64, ActiveConn>>,
+
+    /// Notified when the last active connection is closed
+    all_conns_closed: Notify,
+
+    /// Wakes up tasks when the server starts shutting down; shared by all shards
+    shutdown: ShutdownCoordinator,
 
     /// Timestamp of first accepted connection
     pub first_conn_accepted_ts: Cell<SystemTime>,
 
-    /// Timestamp of last accepted connection
-    pub last_conn_accepted_ts: Cell<SystemTime>,
+    /// Timestamp of last closed connection
+    pub last_conn_closed_ts: Cell<SystemTime>,
 
-    /// Token storage, key is an array of 16 bytes, value is access counter
-    token_table: RefCell<HashMap<[u8; 16], u64>>,
+    /// Token storage
+    token_store: RefCell<DurableTokenStore>,
+
+    /// Checks if a token belongs to this server, tokens of other shards are never stored
+    owns_token: Box<dyn Fn(&Token) -> bool>,
 }
 
 
+/// Connection being served
+struct ActiveConn {
+    peer: SocketAddr,
+    accepted_at: Instant,
+
+    /// Aborts the connection task, set once the task is spawned
+    task: Option<AbortHandle>,
+}
+
+
+/// Connection that was still open when the drain deadline passed
+#[derive(Debug)]
+pub(crate) struct ForceClosedConn {
+    pub conn_id: i64,
+    pub peer: SocketAddr,
+    pub open_for: Duration,
+}
+
+
+/// Counters reported by the `stats` admin command and when the server stops
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct ServerStats {
+    pub requests: i64,
+    pub conns: i64,
+    pub active_conns: i64,
+    pub conns_peak: i64,
+    pub tokens: usize,
+    pub first_conn_accepted_ts: SystemTime,
+    pub last_conn_closed_ts: SystemTime,
+}
+
+
+impl ServerStats {
+
+    /// Adds stats of another shard; the time spans of both are joined, connection peaks are summed
+    pub fn merge(&mut self, other: &ServerStats) {
+        // Shards without connections answer requests forwarded by other shards
+        self.requests += other.requests;
+        self.tokens += other.tokens;
+        if other.conns == 0 {
+            return;
+        }
+        if self.conns == 0 {
+            self.first_conn_accepted_ts = other.first_conn_accepted_ts;
+            self.last_conn_closed_ts = other.last_conn_closed_ts;
+        }
+        self.conns += other.conns;
+        self.active_conns += other.active_conns;
+        self.conns_peak += other.conns_peak;
+        self.first_conn_accepted_ts = self.first_conn_accepted_ts.min(other.first_conn_accepted_ts);
+        self.last_conn_closed_ts = self.last_conn_closed_ts.max(other.last_conn_closed_ts);
+    }
+
+    /// Logs requests per second between the first connection accepted and the last one closed
+    pub fn log(&self) {
+        // Check time elapsed between first and last connection
+        let mut elapsed_sec = self.last_conn_closed_ts.duration_since(self.first_conn_accepted_ts)
+            .unwrap_or_default().as_millis() as f64;
+        elapsed_sec /= 1000.0;
+        let requests_per_sec = self.requests as f64 / elapsed_sec;
+
+        info!("* {} requests over {} connections served in {} seconds, {} requests/sec, parallel conns peak: {}",
+              self.requests, self.conns, &elapsed_sec, &requests_per_sec, self.conns_peak);
+    }
+}
+
+
+impl fmt::Display for ServerStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "requests={} conns={} active_conns={} conns_peak={} tokens={}",
+               self.requests, self.conns, self.active_conns, self.conns_peak, self.tokens)
+    }
+}
+
+
+/// Result of an admin command
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum AdminOutcome {
+    ShuttingDown,
+    Stats(ServerStats),
+
+    /// Token store reloaded, number of loaded tokens
+    Reloaded(usize),
+
+    Issued(Token),
+    Inserted,
+    Revoked,
+}
+
+
+impl AdminOutcome {
+
+    /// Joins outcomes of the same command executed by different shards
+    pub fn merge(self, other: AdminOutcome) -> AdminOutcome {
+        match (self, other) {
+            (AdminOutcome::Stats(mut stats), AdminOutcome::Stats(other)) => {
+                stats.merge(&other);
+                AdminOutcome::Stats(stats)
+            },
+            (AdminOutcome::Reloaded(tokens), AdminOutcome::Reloaded(other)) => AdminOutcome::Reloaded(tokens + other),
+            (outcome, _) => outcome,
+        }
+    }
+
+    /// Reply line sent to the admin client
+    pub fn reply(result: AppResult<AdminOutcome>) -> String {
+        match result {
+            Ok(AdminOutcome::ShuttingDown) => "ok shutting down".to_string(),
+            Ok(AdminOutcome::Stats(stats)) => format!("ok {}", stats),
+            Ok(AdminOutcome::Reloaded(tokens)) => format!("ok reloaded tokens={}", tokens),
+            Ok(AdminOutcome::Issued(token)) => format!("ok issued {}", token_hex(&token)),
+            Ok(AdminOutcome::Inserted) => "ok inserted".to_string(),
+            Ok(AdminOutcome::Revoked) => "ok revoked".to_string(),
+            Err(e) => format!("error {}", e),
+        }
+    }
+}
+
+
+/// Executes admin commands, on all shards of the sharded server
+pub(crate) type AdminHandler = Rc<dyn Fn(AdminCommand) -> LocalBoxFuture<'static, AppResult<AdminOutcome>>>;
+
+
 impl GlobalState {
 
-    /// Initializes global state with default values
-    fn init() -> GlobalState {
+    /// Initializes global state with default values and recovers the token store from `store_dir`;
+    ///
+    /// A new token store is filled with the dummy and seed file tokens owned by this server.
+    pub(crate) fn init(config: &ServerConfig, store_dir: &Path, owns_token: Box<dyn Fn(&Token) -> bool>,
+                       shutdown: ShutdownCoordinator) -> AppResult<GlobalState> {
+
+        let store = DurableTokenStore::open(store_dir)?;
+        info!("* token store {} recovered: {:?}, {} tokens", store_dir.display(), store.recovery(), store.len());
 
         let mut gs = GlobalState {
+            config: config.clone(),
             next_conn_id: Cell::new(0),
             active_conns_cnt: Cell::new(0),
             active_conns_cnt_peak: Cell::new(0),
             requests_cnt: Cell::new(0),
-            is_shutting_down: Cell::new(false),
-            token_table: RefCell::new(HashMap::new()),
-
-            /// Timestamp of first accepted connection
+            active_conns: RefCell::new(HashMap::new()),
+            all_conns_closed: Notify::new(),
+            shutdown,
+            token_store: RefCell::new(store),
             first_conn_accepted_ts: Cell::new(SystemTime::now()),
-
-            /// Timestamp of last accepted connection
-            last_conn_accepted_ts: Cell::new(SystemTime::now()),
-
+            last_conn_closed_ts: Cell::new(SystemTime::now()),
+            owns_token,
         };
 
         fn key_from_u128(i: u128) -> [u8; 16] {
-            let bytes = i.to_le_bytes();
-            bytes
+            i.to_le_bytes()
         }
 
-        // Insert some dummy values into the token table
-        let table = gs.token_table.get_mut();
-        for i in 0_u64..1_000_000 {
-            //tt.entry(&[0_u8, 0, 0, 0, 0, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]).or_insert(0);
-            table.insert(key_from_u128(i as u128), 0);
+        fn entry(access_count: u64) -> TokenEntry {
+            TokenEntry { access_count, expires_at: None, max_uses: None, revoked: false }
         }
 
-        // And 5 valid test keys
-        table.insert([0_u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 1], 0);
-        table.insert([0_u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 2], 0);
-        table.insert([0_u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 3], 0);
-        table.insert([0_u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 4], 0);
-        table.insert([0_u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 5], 100);
+        if !gs.token_store.get_mut().is_empty() {
+            return Ok(gs);
+        }
+
+        let owns_token = &gs.owns_token;
+        let store = gs.token_store.get_mut();
+        let mut seed = |token, access_count| match owns_token(&token) {
+            true => store.insert_entry(token, entry(access_count)),
+            false => Ok(()),
+        };
+
+        // Insert some dummy tokens into the token store
+        for i in 0..config.token_store.dummy_tokens {
+            seed(key_from_u128(i as u128), 0)?;
+        }
+
+        // And the tokens of the seed file, they must differ from the dummy ones
+        if let Some(seed_file) = &config.token_store.seed_file {
+            for seed_token in read_seed_file(seed_file)? {
+                seed(seed_token.token, seed_token.access_count).map_err(|e| match e.kind {
+                    ErrList::AlreadyExists => app_err!(ErrList::InvalidSeedData, Some(format!(
+                        "seed file {}: line {}: token is one of the dummy tokens", seed_file.display(), seed_token.line))),
+                    _ => e,
+                })?;
+            }
+        }
 
-        assert_eq!(table[&[2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]], 0);
-        assert_eq!(table[&[0_u8, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 5]], 100);
-        gs
+        store.snapshot()?;
+        Ok(gs)
     }
 
     /// Updates (increment) next_conn_id, active_conns_cnt, and active_conns_cnt_peak;
     /// 
     /// Returns next_conn_id.
-    fn on_new_conn_get_id(&self) -> i64 {
+    pub(crate) fn on_new_conn_get_id(&self, peer: SocketAddr) -> i64 {
         // Increment next conn id
         let next_conn_id = self.next_conn_id.get();
         self.next_conn_id.set(next_conn_id + 1);
+        self.active_conns.borrow_mut().insert(next_conn_id, ActiveConn { peer, accepted_at: Instant::now(), task: None });
 
         // Increment active_conns_cnt
         let mut active_conns_cnt = self.active_conns_cnt.get();
@@ -121,7 +296,7 @@ impl GlobalState {
         next_conn_id
     }
 
-    fn get_active_conns_cnt(&self) -> i64 {
+    pub(crate) fn get_active_conns_cnt(&self) -> i64 {
         self.active_conns_cnt.get()
     }
 
@@ -129,18 +304,56 @@ impl GlobalState {
         self.requests_cnt.get()
     }
 
+    /// Stores the handle of the task serving a connection, so the connection can be force-closed
+    pub(crate) fn on_conn_task_spawned(&self, conn_id: i64, task: AbortHandle) {
+        if let Some(conn) = self.active_conns.borrow_mut().get_mut(&conn_id) {
+            conn.task = Some(task);
+        }
+    }
+
     /// Decrements `active_conns_cnt`;
     /// Returns updated `active_conns_cnt`;
-    #[allow(unused)]
-    fn on_conn_closed(&self) -> i64 {
+    pub(crate) fn on_conn_closed(&self, conn_id: i64) -> i64 {
         // Decrement active_conns_cnt
         let mut active_conns_cnt = self.active_conns_cnt.get();
         active_conns_cnt -= 1;
         self.active_conns_cnt.set(active_conns_cnt);
-        // println!("  -> conn #{} closed. Active conns: {}", &conn_id, active_conns_cnt);
+        self.active_conns.borrow_mut().remove(&conn_id);
+        self.store_last_conn_closed_ts();
+        if active_conns_cnt == 0 {
+            self.all_conns_closed.notify_waiters();
+        }
         active_conns_cnt
     }
 
+    /// Waits until all connections are closed, but no longer than `timeout`, then aborts tasks
+    /// of the connections that are still open;
+    ///
+    /// Returns the force-closed connections.
+    pub(crate) async fn drain_conns(&self, timeout: Duration) -> Vec<ForceClosedConn> {
+        let _ = time::timeout(timeout, async {
+            loop {
+                let all_closed = self.all_conns_closed.notified();
+                if self.get_active_conns_cnt() == 0 {
+                    break;
+                }
+                all_closed.await;
+            }
+        }).await;
+
+        let mut force_closed: Vec<_> = self.active_conns.borrow_mut().drain()
+            .map(|(conn_id, conn)| {
+                if let Some(task) = conn.task {
+                    task.abort();
+                }
+                ForceClosedConn { conn_id, peer: conn.peer, open_for: conn.accepted_at.elapsed() }
+            })
+            .collect();
+        self.active_conns_cnt.set(0);
+        force_closed.sort_by_key(|conn| conn.conn_id);
+        force_closed
+    }
+
     /// Increments global requests counter
     #[allow(unused)]
     fn inc_requests_cnt(&self) -> i64  {
@@ -151,39 +364,188 @@ impl GlobalState {
     }
 
     /// Checks if server is shutting down
-    #[allow(unused)]
-    fn is_shutting_down(&self) -> bool {
-        self.is_shutting_down.get()
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_requested()
     }
 
-    /// Initializes server shutdown
-    #[allow(unused)]
-    fn init_shutdown(&self) {
-        self.is_shutting_down.set(true);
+    /// Initializes server shutdown, wakes up all tasks waiting for it
+    pub(crate) fn init_shutdown(&self, reason: ShutdownReason) {
+        if self.shutdown.request(reason) {
+            info!("* shutdown requested: {:?}", reason);
+        }
     }
 
-    /// Checks if token is present in the hash table database and increments associated value.
-    /// 
-    /// Returns `None` if token not found or the updated value.
-    #[allow(unused)]
-    fn inc_token_value(&self, token: &[u8; 16]) -> Option<u64> {
-        let mut table = self.token_table.borrow_mut();
-        match table.get_mut(token) {
-            Some(val) => {*val += 1; return Some(*val)},
-            None => return None
+    /// Signal of shutdown start
+    pub(crate) fn shutdown_signal(&self) -> ShutdownSignal {
+        self.shutdown.subscribe()
+    }
+
+    /// Removes a batch of expired, revoked and used up tokens from the token store;
+    ///
+    /// Returns the number of removed tokens.
+    fn sweep_expired_tokens(&self) -> usize {
+        self.token_store.borrow_mut().sweep(now_millis(), TOKEN_SWEEP_BATCH)
+    }
+
+    /// Writes token store changes to disk, periodically as a snapshot
+    pub(crate) fn persist_tokens(&self, snapshot: bool) -> AppResult<()> {
+        let mut store = self.token_store.borrow_mut();
+        if snapshot {
+            store.snapshot()
+        } else {
+            store.sync()
+        }
+    }
+
+    /// Replaces tokens in memory with the ones stored on disk, returns the number of tokens
+    fn reload_tokens(&self) -> AppResult<usize> {
+        let mut store = self.token_store.borrow_mut();
+        store.reload()?;
+        info!("* token store {} reloaded: {:?}, {} tokens", store.dir().display(), store.recovery(), store.len());
+        Ok(store.len())
+    }
+
+    /// Executes an admin command on this server or shard
+    pub(crate) fn handle_admin(&self, cmd: AdminCommand) -> AppResult<AdminOutcome> {
+        match cmd {
+            AdminCommand::Shutdown => {
+                self.init_shutdown(ShutdownReason::AdminCommand);
+                Ok(AdminOutcome::ShuttingDown)
+            },
+            AdminCommand::Stats => Ok(AdminOutcome::Stats(self.stats())),
+            AdminCommand::Reload => self.reload_tokens().map(AdminOutcome::Reloaded),
+            AdminCommand::Issue { limits } => {
+                let token = self.token_store.borrow_mut().issue_where(limits, now_millis(), &*self.owns_token)?;
+                debug!("* token issued, {:?}", limits);
+                Ok(AdminOutcome::Issued(token))
+            },
+            AdminCommand::Insert { token, limits } => {
+                self.token_store.borrow_mut().insert(token, limits, now_millis())?;
+                debug!("* token inserted, {:?}", limits);
+                Ok(AdminOutcome::Inserted)
+            },
+            AdminCommand::Revoke { token } => {
+                self.token_store.borrow_mut().revoke(&token)?;
+                debug!("* token revoked");
+                Ok(AdminOutcome::Revoked)
+            },
+        }
+    }
+
+    /// Processes a request and returns the response
+    pub(crate) fn handle_request(&self, conn_id: i64, req: &Request) -> Response {
+
+        // Increment request counter
+        let _req_total = self.inc_requests_cnt();
+
+        match req {
+            // check the token
+            Request::CheckToken { token, .. } => {
+                trace!("* (conn #{}) received token {:?}", &conn_id, token);
+                let (status, access_count) = self.token_store.borrow_mut().check(token, now_millis());
+                Response::ok(req, status, access_count)
+            },
+            Request::Invalid { request_id, opcode, err } => {
+                trace!("* (conn #{}) invalid request #{} (opcode {:#x}): {:?}", &conn_id, request_id, opcode, err);
+                Response::error(req, *err)
+            },
         }
     }
 
-    fn store_first_conn_ts(&self) {
+    pub(crate) fn store_first_conn_ts(&self) {
         self.first_conn_accepted_ts.set(SystemTime::now());
     }
 
-    fn store_last_conn_ts(&self) {
-        self.last_conn_accepted_ts.set(SystemTime::now());
+    fn store_last_conn_closed_ts(&self) {
+        self.last_conn_closed_ts.set(SystemTime::now());
     }
 
+    pub(crate) fn stats(&self) -> ServerStats {
+        ServerStats {
+            requests: self.get_requests_cnt(),
+            conns: self.next_conn_id.get(),
+            active_conns: self.get_active_conns_cnt(),
+            conns_peak: self.active_conns_cnt_peak.get(),
+            tokens: self.token_store.borrow().len(),
+            first_conn_accepted_ts: self.first_conn_accepted_ts.get(),
+            last_conn_closed_ts: self.last_conn_closed_ts.get(),
+        }
+    }
+
+}
+
+/// Reason a connection was closed
+#[derive(Debug)]
+pub(crate) enum ConnEnd {
+    ClosedByPeer,
+    IdleTimeout,
+    RequestsMaxReached,
+    Shutdown,
+
+    /// Framing error or failed socket read/write
+    Failed(AppErr),
+}
+
+
+/// Answers requests of a persistent connection until it is closed;
+///
+/// Pipelined requests are answered in order, responses carry request ids. Responses to all
+/// requests that are already received are written at once, so pipelining saves syscalls too.
+/// On shutdown the requests that are already received are answered before closing.
+async fn serve_conn(socket: tokio::net::TcpStream, gs: &GlobalState, conn_id: i64) -> ConnEnd {
+    let mut framed = Framed::new(socket, ServerCodec);
+    let mut requests_cnt: u64 = 0;
+    let mut shutdown = gs.shutdown_signal();
+
+    loop {
+        // Wait for the next request; requests already received are answered even if shutdown
+        // is requested at the same time
+        let mut next = tokio::select! {
+            biased;
+            next = time::timeout(Duration::from_millis(gs.config.timeouts.conn_idle_ms), framed.next()) => match next {
+                Ok(next) => next,
+                Err(_) => return ConnEnd::IdleTimeout,
+            },
+            _ = shutdown.recv() => return ConnEnd::Shutdown,
+        };
+
+        // Answer it and all requests that are already received
+        let conn_end = loop {
+            let req = match next {
+                Some(Ok(req)) => req,
+                None => break Some(ConnEnd::ClosedByPeer),
+                Some(Err(e)) => break Some(ConnEnd::Failed(e)),
+            };
+
+            let resp = gs.handle_request(conn_id, &req);
+            requests_cnt += 1;
+            if let Err(e) = framed.feed(resp).await {
+                return ConnEnd::Failed(e);
+            }
+
+            if requests_cnt >= gs.config.limits.conn_requests_max {
+                break Some(ConnEnd::RequestsMaxReached);
+            }
+
+            next = match framed.next().now_or_never() {
+                Some(next) => next,
+                None => break None,  // no more requests received yet
+            };
+        };
+
+        if let Err(e) = framed.flush().await {
+            return ConnEnd::Failed(e);
+        }
+        if let Some(conn_end) = conn_end {
+            return conn_end;
+        }
+        if gs.is_shutting_down() {
+            return ConnEnd::Shutdown;
+        }
+    }
 }
 
+
 /// Accepts new connections in a loop;
 /// Returns Ok(()) if accepting can be resumed in the future or
 /// Err(Other) if not (e.g. due to shutdown).
@@ -197,11 +559,11 @@ async fn accept_new_conns(listener:  &mut tokio::net::TcpListener, gs: &Rc<Globa
         // Check if server is not shutting down
         if gs.is_shutting_down() { 
             warn!("! accept_new_conn() is going to return Err because shutdown detected.");
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "").into()); 
+            return Err(std::io::Error::other("").into());
         }
 
         // Check if number of active connections limit exceeded
-        if gs.get_active_conns_cnt() >= ACTIVE_CONNS_MAX {
+        if gs.get_active_conns_cnt() >= gs.config.limits.active_conns_max {
             warn!("! Connection limit exceeded. Pausing accepting for 1 second...");
             // Sleep for a while
             time::sleep(Duration::from_millis(10)).await;
@@ -210,14 +572,14 @@ async fn accept_new_conns(listener:  &mut tokio::net::TcpListener, gs: &Rc<Globa
         }
 
         // eprintln!("  -> new accept loop started");
-        let (mut socket, _client_addr) = listener.accept().await?;
+        let (socket, client_addr) = listener.accept().await?;
         //socket.set_nodelay(true)?;
         //socket.set_linger(None)?;
 
         // Create a new reference to global state that will be moved to another thread
         let gl_state = gs.clone();
 
-        let conn_id = gl_state.on_new_conn_get_id();
+        let conn_id = gl_state.on_new_conn_get_id(client_addr);
 
         // If this is the first connection, store its timestamp
         if conn_id == 0 {
@@ -227,88 +589,168 @@ async fn accept_new_conns(listener:  &mut tokio::net::TcpListener, gs: &Rc<Globa
 
         // Spawn a new local task for each new connection. Tasks in Tokio are very lightweight. 
         // Under the hood, they require only a single allocation and 64 bytes of memory.
-        tokio::task::spawn_local(async move {
-            let mut buf = [0; 16];
+        let task = tokio::task::spawn_local(async move {
             debug!("  * Conn #: {}", &conn_id);
+            let conn_end = serve_conn(socket, &gl_state, conn_id).await;
+            let active_conn_cnt = gl_state.on_conn_closed(conn_id);
+            match conn_end {
+                ConnEnd::Failed(e) => debug!("* conn #{} failed: {}, active connections: {}",
+                                             &conn_id, e, active_conn_cnt),
+                _ => trace!("* conn #{} closed ({:?}), active connections: {}", &conn_id, conn_end, active_conn_cnt),
+            }
+        });
+        gs.on_conn_task_spawned(conn_id, task.abort_handle());
+    } 
 
-            // Read socket data in a loop
-            loop {
-                match socket.read(&mut buf).await {
-                    // socket closed
-                    Ok(n) if n == 0 => {
-                        let active_conn_cnt = gl_state.on_conn_closed();
-                        
-                        trace!("* conn #{} closed by remote peer, active connections: {}", &conn_id, &active_conn_cnt);
-                        return;
-                    },
-                    // received data size is not 16 bytes
-                    Ok(n) if n != 16 => {
-                        let active_conn_cnt = gl_state.on_conn_closed();
-                        
-                        trace!("* (conn #{}) Err: request size is not 16 bytes ({}), active connections: {}", 
-                                    &conn_id, n, active_conn_cnt);
-                        return;
-                    },
-                    Ok(_) => (),
-                    Err(e) => {
-                        let active_conn_cnt = gl_state.on_conn_closed();
-                        
-                        trace!("* (conn #{}) failed to read from socket; err = {:?}, active connections: {}", 
-                                    &conn_id, e, active_conn_cnt);
-                        return;
-                    }
-                };
+}
+
+
+/// Spawns local tasks that sweep expired tokens and persist the token store until shutdown
+pub(crate) fn spawn_token_store_tasks(gs: &Rc<GlobalState>) {
 
-                // Increment request counter
-                let _req_total = gl_state.inc_requests_cnt();
-
-                // Check if it is not QUIT_MSG
-                if &buf == &QUIT_MSG {
-                    let active_conn_cnt = gl_state.on_conn_closed();
-                    // Store timestamp when the quit message received
-                    gl_state.store_last_conn_ts();
-                    trace!("* (conn #{}) QUIT_MSG received, shutting down, active connections: {}", 
-                    &conn_id, active_conn_cnt);
-                    gl_state.init_shutdown();
+    // Periodically sweep expired tokens that are never checked again, revoked and used up ones
+    let sweeper_state = gs.clone();
+    tokio::task::spawn_local(async move {
+        let mut interval = time::interval(Duration::from_millis(TOKEN_SWEEP_PERIOD));
+        let mut shutdown = sweeper_state.shutdown_signal();
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {},
+                _ = shutdown.recv() => break,
+            }
+            let removed = sweeper_state.sweep_expired_tokens();
+            if removed > 0 {
+                debug!("* {} dead tokens swept", removed);
+            }
+        }
+    });
+
+    // Sync token store changes to disk and take snapshots
+    let persister_state = gs.clone();
+    tokio::task::spawn_local(async move {
+        let mut interval = time::interval(Duration::from_millis(TOKEN_STORE_SYNC_PERIOD));
+        let mut ticks: u64 = 0;
+        let mut shutdown = persister_state.shutdown_signal();
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {},
+                // The last changes are persisted after connections are drained
+                _ = shutdown.recv() => break,
+            }
+            ticks += 1;
+            let snapshot = ticks % (TOKEN_STORE_SNAPSHOT_PERIOD / TOKEN_STORE_SYNC_PERIOD) == 0;
+            if let Err(e) = persister_state.persist_tokens(snapshot) {
+                apptools::log_app_err!(e);
+            }
+        }
+    });
+}
+
+
+/// Spawns a local task that requests shutdown on SIGINT or SIGTERM
+pub(crate) fn spawn_signal_handler(shutdown: ShutdownCoordinator) {
+    tokio::task::spawn_local(async move {
+        match wait_for_signal().await {
+            Ok(reason) => {
+                info!("* {:?} received", reason);
+                shutdown.request(reason);
+            },
+            Err(e) => error!("! failed to listen for signals: {}", e),
+        }
+    });
+}
+
+
+/// Answers admin commands of a connection until it is closed, idle or the server is shutting down
+async fn serve_admin_conn(stream: UnixStream, handler: &AdminHandler, idle_timeout: Duration,
+                          mut shutdown: ShutdownSignal) {
+    let mut framed = Framed::new(stream, admin_codec());
+    loop {
+        let line = tokio::select! {
+            next = time::timeout(idle_timeout, framed.next()) => match next {
+                Ok(Some(Ok(line))) => line,
+                Ok(Some(Err(e))) => {
+                    debug!("* admin conn failed: {}", e);
                     return;
-                }
-                
-                // check the token
-                // (looks like it does not degrade performance much)
-                trace!("* (conn #{}) received token {:?}", &conn_id, &buf);
-                match gl_state.inc_token_value(&buf) {
-                    Some(val) => { trace!("  -> token {:?} was referenced {} times", &buf, val); },
-                    None => { trace!("  -> token {:?} not found", &buf); }
-                }
+                },
+                Ok(None) | Err(_) => return,
+            },
+            _ = shutdown.recv() => return,
+        };
+
+        let result = match line.parse::<AdminCommand>() {
+            Ok(cmd) => {
+                info!("* admin command: {}", cmd.name());
+                handler(cmd).await
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = framed.send(AdminOutcome::reply(result)).await {
+            debug!("* admin conn failed: {}", e);
+            return;
+        }
+    }
+}
 
-                let active_conn_cnt = gl_state.on_conn_closed();
-                
-                trace!("  -> conn #{} closed, active connections: {}", &conn_id, active_conn_cnt);
-                return;        
 
-                // // Write the response
-                // if let Err(e) = socket.write_all(ok_resp.as_bytes()).await {
-                //     eprintln!("failed to write to socket; err = {:?}", e);
-                //     return;
-                // }
+/// Spawns a local task that serves the admin socket until shutdown
+pub(crate) fn spawn_admin_server(listener: AdminListener, handler: AdminHandler, idle_timeout: Duration,
+                                 shutdown: ShutdownSignal) {
+    info!("* admin socket: {}", listener.path().display());
+    tokio::task::spawn_local(async move {
+        let mut listener_shutdown = shutdown.clone();
+        loop {
+            let stream = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = listener_shutdown.recv() => break,
+            };
+            match stream {
+                Ok(stream) => {
+                    let handler = handler.clone();
+                    let shutdown = shutdown.clone();
+                    tokio::task::spawn_local(async move { serve_admin_conn(stream, &handler, idle_timeout, shutdown).await });
+                },
+                Err(e) => apptools::log_app_err!(e),
             }
-        });
-    } 
+        }
+    });
+}
+
 
+/// Drains connections on shutdown and reports the ones that had to be force-closed
+pub(crate) async fn drain_conns(gs: &GlobalState) {
+    let timeout = gs.config.timeouts.shutdown_drain_ms;
+    let force_closed = gs.drain_conns(Duration::from_millis(timeout)).await;
+    if force_closed.is_empty() {
+        return;
+    }
+    warn!("! {} connections force-closed after {} millis drain timeout", force_closed.len(), timeout);
+    for conn in &force_closed {
+        warn!("  * conn #{} from {}, open for {:?}", conn.conn_id, conn.peer, conn.open_for);
+    }
 }
 
 
-pub async fn run_server(port: u16) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn run_server(config: &ServerConfig) -> Result<(), Box<dyn std::error::Error>> {
 
     #[allow(unused)]
-    let mut listener = TcpListener::bind(format!("0.0.0.0:{}", &port)).await?;
+    let mut listener = TcpListener::bind(config.server.bind_addr).await?;
 
-    info!("== Token Checker Server listening on port {} ==", &port);
+    info!("== Token Checker Server listening on {} ==", config.server.bind_addr);
 
     // Create global state and wrap it into Rc so that it can be shared between tasks
-    let gl_state = Rc::new( GlobalState::init());
-
-    // Accept new connections in a loop and periodically check for a shutdown request
+    let shutdown = ShutdownCoordinator::new();
+    let gl_state = Rc::new( GlobalState::init(config, &config.token_store.dir, Box::new(|_| true), shutdown.clone())?);
+    spawn_token_store_tasks(&gl_state);
+    spawn_signal_handler(shutdown);
+
+    let admin_state = gl_state.clone();
+    let admin_handler: AdminHandler = Rc::new(move |cmd| futures::future::ready(admin_state.handle_admin(cmd)).boxed_local());
+    spawn_admin_server(AdminListener::bind(&config.server.admin_socket)?, admin_handler,
+                       Duration::from_millis(config.timeouts.admin_idle_ms), gl_state.shutdown_signal());
+
+    // Accept new connections in a loop until shutdown is requested
+    let mut shutdown = gl_state.shutdown_signal();
     loop {
 
         // Select macro waits for any of the tasks to complete and executes its corresponding handler;
@@ -330,52 +772,207 @@ pub async fn run_server(port: u16) -> Result<(), Box<dyn std::error::Error>> {
                 }
             },
     
-            // Wait for shutdown, block until `gl_state.is_shutting_down()` returns true
-            _result = async { 
-
-                loop {
-                    // check if server is shutting down
-                    if gl_state.is_shutting_down() {
-                        debug!("  * is_shutting_down() returned true in run_server() loop, breaking ...");
-                        // break waiting loop
-                        break;
-                    }
-                    // retry waiting for SHUTDOWN_POLLING_TIME
-                    trace!("  -> waiting for shutdown ({} millis) (re)started", SHUTDOWN_POLLING_TIME);
-                    time::sleep(Duration::from_millis(SHUTDOWN_POLLING_TIME)).await 
-                }
-                
-            } => { 
-                // break accepting loop
-                break; 
+            // Stop accepting as soon as shutdown is requested
+            _reason = shutdown.recv() => {
+                debug!("  * shutdown requested, stopping accepting ...");
+                break;
             },
         }
 
     }
 
-    
-    // Wait until all connections are closed
-    for _ in 0..20 {
-        if gl_state.get_active_conns_cnt() == 0 { break; }
-        time::sleep(Duration::from_millis(100)).await;
+    // Stop listening, then let connections answer requests already received
+    drop(listener);
+    drain_conns(&gl_state).await;
+
+    // Persist changes made after the last sync
+    gl_state.persist_tokens(false)?;
+
+    gl_state.stats().log();
+
+    Ok(())
+
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use tokio::net::TcpStream;
+    use tokio::task::LocalSet;
+
+    use poc1_tokio_playground::token_proto::{ClientCodec, TokenStatus};
+    use poc1_tokio_playground::token_store::TokenLimits;
+
+    use super::*;
+
+    /// Number of dummy tokens, the little-endian bytes of `0..DUMMY_TOKENS`
+    const DUMMY_TOKENS: u64 = 10;
+
+    fn dummy_token(i: u64) -> Token {
+        (i as u128).to_le_bytes()
     }
-    // Assert that all connections are closed
-    assert_eq!(gl_state.get_active_conns_cnt(), 0);
 
-    // Check time elapsed between first and last connection
-    let first_conn_ts = gl_state.first_conn_accepted_ts.get();
-    let last_conn_ts = gl_state.last_conn_accepted_ts.get();
+    /// State with a new token store in a temporary directory
+    fn init_state(name: &str, mut config: ServerConfig) -> GlobalState {
+        let dir = std::env::temp_dir().join(format!("srv_for_bench_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        config.token_store.dir = dir.clone();
+        config.token_store.dummy_tokens = DUMMY_TOKENS;
+        GlobalState::init(&config, &dir, Box::new(|_| true), ShutdownCoordinator::new()).unwrap()
+    }
 
-    let mut elapsed_sec = last_conn_ts.duration_since(first_conn_ts).unwrap().as_millis() as f64;
-    elapsed_sec /= 1000.0;
-    let total_requests_served = gl_state.get_requests_cnt() as f64;
-    let requests_per_sec = total_requests_served / elapsed_sec;
+    /// Serves connections on a loopback port
+    async fn start_server(name: &str, config: ServerConfig) -> (Rc<GlobalState>, SocketAddr) {
+        let gs = Rc::new(init_state(name, config));
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept_state = gs.clone();
+        tokio::task::spawn_local(async move {
+            while accept_new_conns(&mut listener, &accept_state).await.is_ok() {}
+        });
+        (gs, addr)
+    }
 
-    let active_conns_peak = gl_state.active_conns_cnt_peak.get();
+    fn stop_server(gs: &GlobalState) {
+        gs.init_shutdown(ShutdownReason::AdminCommand);
+        let _ = std::fs::remove_dir_all(&gs.config.token_store.dir);
+    }
 
-    info!("* {} connections served in {} seconds, {} conns/sec, parallel conns peak: {}", 
-          gl_state.get_requests_cnt(), &elapsed_sec, &requests_per_sec, active_conns_peak);
+    async fn connect(addr: SocketAddr) -> Framed<TcpStream, ClientCodec> {
+        Framed::new(TcpStream::connect(addr).await.unwrap(), ClientCodec)
+    }
 
-    Ok(())
+    /// Sends token checks with request ids `first..first + cnt` in a single write
+    async fn send_checks(client: &mut Framed<TcpStream, ClientCodec>, first: u32, cnt: u32) {
+        for request_id in first..first + cnt {
+            let token = dummy_token(request_id as u64 % (DUMMY_TOKENS + 1));
+            client.feed(Request::CheckToken { request_id, token }).await.unwrap();
+        }
+        client.flush().await.unwrap();
+    }
+
+    /// Next response, `None` if the server closed the connection
+    async fn next_response(client: &mut Framed<TcpStream, ClientCodec>) -> Option<Response> {
+        let next = time::timeout(Duration::from_secs(5), client.next()).await.expect("no response in 5 seconds");
+        next.map(|resp| resp.unwrap())
+    }
+
+    #[tokio::test]
+    async fn pipelined_responses_in_order() {
+        LocalSet::new().run_until(async {
+            let (gs, addr) = start_server("pipelined", ServerConfig::default()).await;
+            let mut client = connect(addr).await;
+
+            send_checks(&mut client, 0, 100).await;
+            for request_id in 0..100 {
+                let resp = next_response(&mut client).await.unwrap();
+                assert_eq!(resp.request_id, request_id);
+                let expected = match request_id as u64 % (DUMMY_TOKENS + 1) {
+                    DUMMY_TOKENS => TokenStatus::Unknown,
+                    _ => TokenStatus::Valid,
+                };
+                assert_eq!(resp.status, expected, "request #{}", request_id);
+            }
+            assert_eq!(gs.stats().requests, 100);
+            stop_server(&gs);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn idle_conn_is_closed() {
+        LocalSet::new().run_until(async {
+            let mut config = ServerConfig::default();
+            config.timeouts.conn_idle_ms = 100;
+            let (gs, addr) = start_server("idle", config).await;
+            let mut client = connect(addr).await;
+
+            send_checks(&mut client, 0, 1).await;
+            assert!(next_response(&mut client).await.is_some());
+            let idle_since = Instant::now();
+            assert!(next_response(&mut client).await.is_none());
+            assert!(idle_since.elapsed() >= Duration::from_millis(90));
+            stop_server(&gs);
+        }).await;
+    }
 
+    #[tokio::test]
+    async fn conn_is_closed_after_requests_max() {
+        LocalSet::new().run_until(async {
+            let mut config = ServerConfig::default();
+            config.limits.conn_requests_max = 5;
+            let (gs, addr) = start_server("requests_max", config).await;
+            let mut client = connect(addr).await;
+
+            send_checks(&mut client, 0, 3).await;
+            for request_id in 0..3 {
+                assert_eq!(next_response(&mut client).await.unwrap().request_id, request_id);
+            }
+            send_checks(&mut client, 3, 2).await;
+            for request_id in 3..5 {
+                assert_eq!(next_response(&mut client).await.unwrap().request_id, request_id);
+            }
+            assert!(next_response(&mut client).await.is_none());
+            stop_server(&gs);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn received_requests_are_answered_on_shutdown() {
+        let gs = init_state("drain", ServerConfig::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = connect(listener.local_addr().unwrap()).await;
+        let (socket, _) = listener.accept().await.unwrap();
+
+        // Requests are received and shutdown is requested before the connection is served
+        send_checks(&mut client, 0, 50).await;
+        socket.readable().await.unwrap();
+        gs.init_shutdown(ShutdownReason::AdminCommand);
+        let conn_end = serve_conn(socket, &gs, 0).await;
+        assert!(matches!(conn_end, ConnEnd::Shutdown), "{:?}", conn_end);
+
+        for request_id in 0..50 {
+            assert_eq!(next_response(&mut client).await.unwrap().request_id, request_id);
+        }
+        assert!(next_response(&mut client).await.is_none());
+        stop_server(&gs);
+    }
+
+    #[test]
+    fn admin_commands_manage_tokens() {
+        let gs = init_state("admin", ServerConfig::default());
+        let check = |token| gs.handle_request(0, &Request::CheckToken { request_id: 0, token }).status;
+
+        let token = match gs.handle_admin(AdminCommand::Issue { limits: TokenLimits::default() }).unwrap() {
+            AdminOutcome::Issued(token) => token,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(check(token), TokenStatus::Valid);
+
+        let limits = TokenLimits { ttl_ms: None, max_uses: Some(1) };
+        assert!(matches!(gs.handle_admin(AdminCommand::Insert { token: [7; 16], limits }), Ok(AdminOutcome::Inserted)));
+        assert_eq!(gs.handle_admin(AdminCommand::Insert { token: [7; 16], limits }).unwrap_err().kind, ErrList::AlreadyExists);
+        assert!(matches!(gs.handle_admin(AdminCommand::Revoke { token }), Ok(AdminOutcome::Revoked)));
+        assert_eq!(check(token), TokenStatus::Revoked);
+        assert_eq!(check([7; 16]), TokenStatus::Valid);
+        stop_server(&gs);
+    }
+
+    #[test]
+    fn seed_token_colliding_with_dummy_token() {
+        let dir = std::env::temp_dir().join(format!("srv_for_bench_seed_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let seed_file = dir.join("seed_tokens.txt");
+        std::fs::write(&seed_file, format!("ffffffffffffffffffffffffffffff00\n\n{}\n", token_hex(&dummy_token(3)))).unwrap();
+
+        let mut config = ServerConfig::default();
+        config.token_store.seed_file = Some(seed_file);
+        config.token_store.dummy_tokens = DUMMY_TOKENS;
+        let e = GlobalState::init(&config, &dir.join("store"), Box::new(|_| true), ShutdownCoordinator::new())
+            .err().unwrap();
+        assert_eq!(e.kind, ErrList::InvalidSeedData);
+        assert!(e.msg.unwrap().contains("line 3: "));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
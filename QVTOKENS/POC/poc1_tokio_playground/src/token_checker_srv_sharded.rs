@@ -0,0 +1,472 @@
+//! Multi-core token checker server: N single-threaded shards, each owns a slice of the token space
+//!
+//! Every shard is a single-threaded server (`GlobalState` on a current-thread runtime with a `LocalSet`)
+//! running on its own thread. Shards accept connections on their own SO_REUSEPORT listeners bound
+//! to the same port, so the kernel spreads connections between them. The hash of a token picks
+//! the shard that stores it, see `token_shard()`; requests for tokens of other shards are forwarded
+//! to the owner over a channel and answered in request order. Each shard persists its tokens
+//! to its own directory, so the number of shards must not change between restarts.
+//! Shard #0 serves the admin socket and passes `stats` and `reload` commands to all shards,
+//! `insert` and `revoke` to the owner of the token; an issued token is inserted by its owner.
+
+
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::rc::Rc;
+use std::thread;
+
+use futures::future::join_all;
+use futures::stream::FuturesOrdered;
+use futures::{FutureExt, SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::LocalSet;
+use tokio::time::{self, Duration};
+use tokio_util::codec::Framed;
+
+use log::*;
+
+use apptools::app_err;
+
+use poc1_tokio_playground::admin::{AdminCommand, AdminListener};
+use poc1_tokio_playground::app_err_decl::{AppResult, ErrList};
+use poc1_tokio_playground::config::ServerConfig;
+use poc1_tokio_playground::shutdown::{ShutdownCoordinator, ShutdownReason};
+use poc1_tokio_playground::token_proto::{Request, Response, ServerCodec};
+use poc1_tokio_playground::token_store::{random_token, token_shard};
+
+use crate::token_checker_srv_for_bench::{GlobalState, ServerStats, ConnEnd, AdminHandler, AdminOutcome,
+                                         spawn_token_store_tasks, spawn_signal_handler, spawn_admin_server, drain_conns};
+
+
+/// Maximum number of pending connections of a shard listener
+const LISTEN_BACKLOG: u32 = 1024;
+
+
+/// Message sent to a shard by other shards
+enum ShardMsg {
+
+    /// Request forwarded to the shard that owns its token
+    Forwarded {
+        conn_id: i64,
+        req: Request,
+        reply: oneshot::Sender<Response>,
+    },
+
+    /// Admin command passed by shard #0
+    Admin {
+        cmd: AdminCommand,
+        reply: oneshot::Sender<AppResult<AdminOutcome>>,
+    },
+}
+
+
+struct Shard {
+    id: usize,
+
+    /// State of this shard, stores only tokens owned by it
+    gs: Rc<GlobalState>,
+
+    /// Channels of messages to shards, indexed by shard id
+    peers: Vec<mpsc::UnboundedSender<ShardMsg>>,
+}
+
+
+impl Shard {
+
+    /// Processes a request locally or forwards it to the shard that owns its token
+    async fn handle_request(&self, conn_id: i64, req: Request) -> Response {
+        let owner = req.token().map_or(self.id, |token| token_shard(token, self.peers.len()));
+        if owner == self.id {
+            return self.gs.handle_request(conn_id, &req);
+        }
+
+        let unavailable = Response::error(&req, ErrList::ShardUnavailable);
+        let (reply, response) = oneshot::channel();
+        if self.peers[owner].send(ShardMsg::Forwarded { conn_id, req, reply }).is_err() {
+            return unavailable;
+        }
+        response.await.unwrap_or(unavailable)
+    }
+
+    /// Executes an admin command on the shards it concerns and joins their outcomes
+    async fn handle_admin(&self, cmd: AdminCommand) -> AppResult<AdminOutcome> {
+        let shards = self.peers.len();
+        match cmd {
+            // Shutdown is requested once for all shards
+            AdminCommand::Shutdown => self.gs.handle_admin(cmd),
+            // Issued tokens are inserted by their owners, so they are spread over shards like the other ones;
+            // the token is drawn again on a collision, as `TokenStore::issue_where()` does
+            AdminCommand::Issue { limits } => loop {
+                let token = random_token()?;
+                let owner = token_shard(&token, shards);
+                match self.on_shards(owner..owner + 1, AdminCommand::Insert { token, limits }).await {
+                    Ok(_) => return Ok(AdminOutcome::Issued(token)),
+                    Err(e) if e.kind == ErrList::AlreadyExists => continue,
+                    Err(e) => return Err(e),
+                }
+            },
+            AdminCommand::Insert { token, .. } | AdminCommand::Revoke { token } => {
+                let owner = token_shard(&token, shards);
+                self.on_shards(owner..owner + 1, cmd).await
+            },
+            AdminCommand::Stats | AdminCommand::Reload => self.on_shards(0..shards, cmd).await,
+        }
+    }
+
+    /// Executes an admin command on the shards and joins their outcomes
+    async fn on_shards(&self, shards: Range<usize>, cmd: AdminCommand) -> AppResult<AdminOutcome> {
+        let outcomes = join_all(shards.map(|id| {
+            let (reply, outcome) = oneshot::channel();
+            let sent = self.peers[id].send(ShardMsg::Admin { cmd, reply });
+            async move {
+                let unavailable = || app_err!(ErrList::ShardUnavailable, Some(format!("shard #{}", id)));
+                sent.map_err(|_| unavailable())?;
+                outcome.await.map_err(|_| unavailable())?
+            }
+        })).await;
+
+        let mut joined: Option<AdminOutcome> = None;
+        for outcome in outcomes {
+            let outcome = outcome?;
+            joined = Some(match joined {
+                Some(joined) => joined.merge(outcome),
+                None => outcome,
+            });
+        }
+        Ok(joined.expect("at least one shard"))
+    }
+
+    /// Answers messages of other shards until the shard is dropped
+    async fn serve_peers(&self, mut messages: mpsc::UnboundedReceiver<ShardMsg>) {
+        while let Some(msg) = messages.recv().await {
+            // The requesting connection may be closed already
+            match msg {
+                ShardMsg::Forwarded { conn_id, req, reply } => {
+                    let _ = reply.send(self.gs.handle_request(conn_id, &req));
+                },
+                ShardMsg::Admin { cmd, reply } => {
+                    let _ = reply.send(self.gs.handle_admin(cmd));
+                },
+            }
+        }
+    }
+}
+
+
+/// Answers requests of a persistent connection until it is closed;
+///
+/// Same as the single-threaded `serve_conn()`, but responses of already received requests
+/// are awaited together, so forwarded requests of a batch are processed by their shards in parallel.
+async fn serve_conn(socket: TcpStream, shard: &Shard, conn_id: i64) -> ConnEnd {
+    let mut framed = Framed::new(socket, ServerCodec);
+    let mut requests_cnt: u64 = 0;
+    let mut shutdown = shard.gs.shutdown_signal();
+
+    loop {
+        // Wait for the next request; requests already received are answered even if shutdown
+        // is requested at the same time
+        let mut next = tokio::select! {
+            biased;
+            next = time::timeout(Duration::from_millis(shard.gs.config.timeouts.conn_idle_ms), framed.next()) => match next {
+                Ok(next) => next,
+                Err(_) => return ConnEnd::IdleTimeout,
+            },
+            _ = shutdown.recv() => return ConnEnd::Shutdown,
+        };
+
+        // Start processing it and all requests that are already received
+        let mut responses = FuturesOrdered::new();
+        let conn_end = loop {
+            let req = match next {
+                Some(Ok(req)) => req,
+                None => break Some(ConnEnd::ClosedByPeer),
+                Some(Err(e)) => break Some(ConnEnd::Failed(e)),
+            };
+
+            responses.push_back(shard.handle_request(conn_id, req));
+            requests_cnt += 1;
+            if requests_cnt >= shard.gs.config.limits.conn_requests_max {
+                break Some(ConnEnd::RequestsMaxReached);
+            }
+
+            next = match framed.next().now_or_never() {
+                Some(next) => next,
+                None => break None,  // no more requests received yet
+            };
+        };
+
+        while let Some(resp) = responses.next().await {
+            if let Err(e) = framed.feed(resp).await {
+                return ConnEnd::Failed(e);
+            }
+        }
+        if let Err(e) = framed.flush().await {
+            return ConnEnd::Failed(e);
+        }
+        if let Some(conn_end) = conn_end {
+            return conn_end;
+        }
+        if shard.gs.is_shutting_down() {
+            return ConnEnd::Shutdown;
+        }
+    }
+}
+
+
+/// Accepts new connections of a shard in a loop
+async fn accept_new_conns(listener: TcpListener, shard: &Rc<Shard>) {
+    loop {
+        // Check if number of active connections limit exceeded
+        if shard.gs.get_active_conns_cnt() >= shard.gs.config.limits.active_conns_max {
+            time::sleep(Duration::from_millis(10)).await;
+            continue;
+        }
+
+        let (socket, client_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("  * shard #{} accept error: {}, retrying ...", shard.id, e);
+                time::sleep(Duration::from_millis(100)).await;
+                continue;
+            },
+        };
+
+        let conn_id = shard.gs.on_new_conn_get_id(client_addr);
+        if conn_id == 0 {
+            shard.gs.store_first_conn_ts();
+        }
+
+        let conn_shard = shard.clone();
+        let task = tokio::task::spawn_local(async move {
+            let shard = conn_shard;
+            let conn_end = serve_conn(socket, &shard, conn_id).await;
+            let active_conn_cnt = shard.gs.on_conn_closed(conn_id);
+            match conn_end {
+                ConnEnd::Failed(e) => debug!("* shard #{} conn #{} failed: {}, active connections: {}",
+                                             shard.id, &conn_id, e, active_conn_cnt),
+                _ => trace!("* shard #{} conn #{} closed ({:?}), active connections: {}",
+                            shard.id, &conn_id, conn_end, active_conn_cnt),
+            }
+        });
+        shard.gs.on_conn_task_spawned(conn_id, task.abort_handle());
+    }
+}
+
+
+/// Binds a listener that shares the port with listeners of other shards
+fn bind_reuseport(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    socket.set_reuseport(true)?;
+    socket.bind(addr)?;
+    socket.listen(LISTEN_BACKLOG)
+}
+
+
+/// Runs a shard until shutdown, returns its stats
+async fn run_shard(id: usize, config: &ServerConfig, peers: Vec<mpsc::UnboundedSender<ShardMsg>>,
+                   messages: mpsc::UnboundedReceiver<ShardMsg>, shutdown: ShutdownCoordinator) -> AppResult<ServerStats> {
+
+    let shards = peers.len();
+    let store_dir = config.token_store.dir.join(format!("shard-{}-of-{}", id, shards));
+    let owns_token = Box::new(move |token: &_| token_shard(token, shards) == id);
+    let gs = Rc::new(GlobalState::init(config, &store_dir, owns_token, shutdown.clone())?);
+    spawn_token_store_tasks(&gs);
+    // Signals are handled once for all shards
+    if id == 0 {
+        spawn_signal_handler(shutdown);
+    }
+    let shard = Rc::new(Shard { id, gs, peers });
+
+    let listener = bind_reuseport(config.server.bind_addr)?;
+    info!("== Token Checker Server shard #{} of {} listening on {} ==", id, shards, config.server.bind_addr);
+
+    let peers_shard = shard.clone();
+    tokio::task::spawn_local(async move { peers_shard.serve_peers(messages).await });
+
+    // Admin commands are handled once for all shards
+    if id == 0 {
+        let admin_shard = shard.clone();
+        let admin_handler: AdminHandler = Rc::new(move |cmd| {
+            let shard = admin_shard.clone();
+            async move { shard.handle_admin(cmd).await }.boxed_local()
+        });
+        spawn_admin_server(AdminListener::bind(&config.server.admin_socket)?, admin_handler,
+                           Duration::from_millis(config.timeouts.admin_idle_ms), shard.gs.shutdown_signal());
+    }
+
+    // Accept new connections until shutdown is requested
+    let mut shutdown = shard.gs.shutdown_signal();
+    tokio::select! {
+        _ = accept_new_conns(listener, &shard) => {},
+        _ = shutdown.recv() => {},
+    }
+    debug!("  * shard #{} is shutting down", id);
+
+    // Messages of other shards are served until this shard is dropped
+    drain_conns(&shard.gs).await;
+
+    // Persist changes made after the last sync
+    shard.gs.persist_tokens(false)?;
+    Ok(shard.gs.stats())
+}
+
+
+/// Runs `config.shards()` shards on their own threads until shutdown
+pub fn run_server(config: &ServerConfig) -> Result<(), Box<dyn std::error::Error>> {
+
+    let shards = config.shards();
+    let shutdown = ShutdownCoordinator::new();
+    let (peers, receivers): (Vec<_>, Vec<_>) = (0..shards).map(|_| mpsc::unbounded_channel()).unzip();
+
+    let mut handles = Vec::with_capacity(shards);
+    for (id, messages) in receivers.into_iter().enumerate() {
+        let peers = peers.clone();
+        let shutdown = shutdown.clone();
+        let config = config.clone();
+        let handle = thread::Builder::new().name(format!("shard-{}", id)).spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            let local = LocalSet::new();
+            let result = local.block_on(&rt, run_shard(id, &config, peers, messages, shutdown.clone()));
+            // Stop other shards if this one failed
+            if result.is_err() {
+                shutdown.request(ShutdownReason::ShardFailed);
+            }
+            result
+        })?;
+        handles.push(handle);
+    }
+    drop(peers);
+
+    let mut stats: Option<ServerStats> = None;
+    let mut failed = 0;
+    for (id, handle) in handles.into_iter().enumerate() {
+        match handle.join() {
+            Ok(Ok(shard_stats)) => {
+                debug!("* shard #{} stats: {:?}", id, shard_stats);
+                match &mut stats {
+                    Some(stats) => stats.merge(&shard_stats),
+                    None => stats = Some(shard_stats),
+                }
+            },
+            Ok(Err(e)) => {
+                apptools::log_app_err!(e);
+                failed += 1;
+            },
+            Err(_) => {
+                error!("! shard #{} panicked", id);
+                failed += 1;
+            },
+        }
+    }
+
+    if let Some(stats) = stats {
+        stats.log();
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} shards failed", failed, shards).into());
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use tokio::net::UnixStream;
+    use tokio_util::codec::LinesCodec;
+
+    use poc1_tokio_playground::admin::admin_codec;
+    use poc1_tokio_playground::token_proto::{ClientCodec, Token, TokenStatus, parse_token_hex, token_hex};
+
+    use super::*;
+
+    /// Number of dummy tokens, the little-endian bytes of `0..DUMMY_TOKENS`
+    const DUMMY_TOKENS: u64 = 10;
+
+    fn dummy_token(i: u64) -> Token {
+        (i as u128).to_le_bytes()
+    }
+
+    /// Sends an admin command, returns the reply
+    async fn admin(client: &mut Framed<UnixStream, LinesCodec>, cmd: &str) -> String {
+        client.send(cmd).await.unwrap();
+        client.next().await.unwrap().unwrap()
+    }
+
+    async fn check(client: &mut Framed<TcpStream, ClientCodec>, request_id: u32, token: Token) -> Response {
+        client.send(Request::CheckToken { request_id, token }).await.unwrap();
+        let resp = client.next().await.unwrap().unwrap();
+        assert_eq!(resp.request_id, request_id);
+        resp
+    }
+
+    #[tokio::test]
+    async fn two_shards_over_loopback() {
+        let dir = std::env::temp_dir().join(format!("srv_sharded_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = ServerConfig::default();
+        config.server.shards = 2;
+        config.server.bind_addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        config.server.admin_socket = dir.join("admin.sock");
+        config.token_store.dir = dir.join("store");
+        config.token_store.dummy_tokens = DUMMY_TOKENS;
+
+        let server_config = config.clone();
+        let server = thread::spawn(move || run_server(&server_config).map_err(|e| e.to_string()));
+        while !config.server.admin_socket.exists() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut admin_client = Framed::new(UnixStream::connect(&config.server.admin_socket).await.unwrap(), admin_codec());
+
+        // Tokens of both shards are checked over every connection, the counters are shared
+        let owners: Vec<_> = (0..DUMMY_TOKENS).map(|i| token_shard(&dummy_token(i), 2)).collect();
+        assert!(owners.contains(&0) && owners.contains(&1));
+        let mut request_id = 0;
+        for conn in 0..4 {
+            let mut client = Framed::new(TcpStream::connect(config.server.bind_addr).await.unwrap(), ClientCodec);
+            for i in 0..DUMMY_TOKENS {
+                let resp = check(&mut client, request_id, dummy_token(i)).await;
+                assert_eq!((resp.status, resp.access_count), (TokenStatus::Valid, conn + 1), "token {}", i);
+                request_id += 1;
+            }
+        }
+
+        // Issued tokens are spread over shards, 2^-31 chance that all 32 land on the same one
+        let mut issued = Vec::new();
+        for _ in 0..32 {
+            let reply = admin(&mut admin_client, "issue").await;
+            issued.push(parse_token_hex(reply.strip_prefix("ok issued ").unwrap()).unwrap());
+        }
+        let owners: Vec<_> = issued.iter().map(|token| token_shard(token, 2)).collect();
+        assert!(owners.contains(&0) && owners.contains(&1), "{:?}", owners);
+
+        // Inserted and revoked tokens are passed to their owners
+        let inserted = (1..).map(|i: u8| [i; 16]).find(|token| token_shard(token, 2) == 1).unwrap();
+        let inserted_hex = token_hex(&inserted);
+        assert_eq!(admin(&mut admin_client, &format!("insert {}", inserted_hex)).await, "ok inserted");
+
+        let mut client = Framed::new(TcpStream::connect(config.server.bind_addr).await.unwrap(), ClientCodec);
+        for token in issued.iter().chain(Some(&inserted)) {
+            assert_eq!(check(&mut client, request_id, *token).await.status, TokenStatus::Valid);
+            request_id += 1;
+        }
+
+        // Stats of both shards are merged
+        let stats = admin(&mut admin_client, "stats").await;
+        assert!(stats.contains(&format!(" requests={} ", request_id)), "{}", stats);
+        assert!(stats.contains(&format!(" tokens={}", DUMMY_TOKENS + 33)), "{}", stats);
+
+        assert_eq!(admin(&mut admin_client, &format!("revoke {}", inserted_hex)).await, "ok revoked");
+        assert_eq!(check(&mut client, request_id, inserted).await.status, TokenStatus::Revoked);
+
+        assert_eq!(admin(&mut admin_client, "shutdown").await, "ok shutting down");
+        assert_eq!(server.join().unwrap(), Ok(()));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
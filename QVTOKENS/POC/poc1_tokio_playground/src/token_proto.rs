@@ -0,0 +1,446 @@
+//! Token checker binary protocol
+//!
+//! Each frame starts with a fixed header, all integers are big-endian:
+//!
+//! | offset | size | field          |
+//! |--------|------|----------------|
+//! | 0      | 1    | version        |
+//! | 1      | 1    | opcode         |
+//! | 2      | 4    | request id     |
+//! | 6      | 4    | payload length |
+//!
+//! The header layout never changes, so a frame of any version can be skipped;
+//! the version only defines the payload format.
+//!
+//! A response echoes the request id and the request opcode with `RESPONSE_FLAG` set,
+//! its payload is `status: u8, access counter: u64, error code: u32`.
+//!
+//! The protocol only checks tokens, the token store is managed over the admin socket (`admin.rs`).
+
+
+use std::convert::TryFrom;
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use apptools::app_err;
+
+use crate::app_err_decl::{AppErr, ErrList};
+
+
+/// Current protocol version
+pub const PROTO_VERSION: u8 = 1;
+
+/// Frame header length
+pub const HEADER_LEN: usize = 10;
+
+/// Maximum payload length, a longer frame closes the connection
+pub const MAX_PAYLOAD_LEN: usize = 64 * 1024;
+
+/// Set in the opcode of every response
+pub const RESPONSE_FLAG: u8 = 0x80;
+
+/// Length of a token
+pub const TOKEN_LEN: usize = 16;
+
+/// Length of a response payload
+pub const RESPONSE_PAYLOAD_LEN: usize = 13;
+
+/// Error code field value of a response without error
+pub const NO_ERR_CODE: u32 = u32::MAX;
+
+/// Token is an array of 16 bytes
+pub type Token = [u8; TOKEN_LEN];
+
+
+/// Request opcodes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Opcode {
+
+    /// Payload: token; increments the token access counter
+    CheckToken = 0x01,
+}
+
+
+impl Opcode {
+
+    pub fn from_u8(val: u8) -> Option<Opcode> {
+        match val {
+            0x01 => Some(Opcode::CheckToken),
+            _ => None,
+        }
+    }
+}
+
+
+/// Token status reported in responses
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TokenStatus {
+    Valid = 0,
+    Unknown = 1,
+    Expired = 2,
+    Revoked = 3,
+
+    /// Request failed, see the response error code
+    Error = 0xFF,
+}
+
+
+impl TokenStatus {
+
+    pub fn from_u8(val: u8) -> Option<TokenStatus> {
+        match val {
+            0 => Some(TokenStatus::Valid),
+            1 => Some(TokenStatus::Unknown),
+            2 => Some(TokenStatus::Expired),
+            3 => Some(TokenStatus::Revoked),
+            0xFF => Some(TokenStatus::Error),
+            _ => None,
+        }
+    }
+}
+
+
+/// Parses a token written as 32 hex digits
+pub fn parse_token_hex(hex: &str) -> Option<Token> {
+    if hex.len() != TOKEN_LEN * 2 || !hex.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut token = [0_u8; TOKEN_LEN];
+    for (byte, digits) in token.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+    }
+    Some(token)
+}
+
+
+/// Writes a token as 32 hex digits, see `parse_token_hex()`
+pub fn token_hex(token: &Token) -> String {
+    token.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+/// Decoded request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+
+    CheckToken { request_id: u32, token: Token },
+
+    /// Complete frame that can't be processed, it must be answered with an error response;
+    /// the connection can continue
+    Invalid { request_id: u32, opcode: u8, err: ErrList },
+}
+
+
+impl Request {
+
+    pub fn request_id(&self) -> u32 {
+        match self {
+            Request::CheckToken { request_id, .. } => *request_id,
+            Request::Invalid { request_id, .. } => *request_id,
+        }
+    }
+
+    /// Token the request refers to
+    pub fn token(&self) -> Option<&Token> {
+        match self {
+            Request::CheckToken { token, .. } => Some(token),
+            Request::Invalid { .. } => None,
+        }
+    }
+
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Request::CheckToken { .. } => Opcode::CheckToken as u8,
+            Request::Invalid { opcode, .. } => *opcode,
+        }
+    }
+}
+
+
+/// Response to a request
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Response {
+
+    pub request_id: u32,
+
+    /// Request opcode, without `RESPONSE_FLAG`
+    pub opcode: u8,
+
+    pub status: TokenStatus,
+
+    /// Token access counter after the request
+    pub access_count: u64,
+
+    /// Code of the error the request failed with, see `ErrList`
+    pub err_code: Option<u32>,
+}
+
+
+impl Response {
+
+    /// Successful response to a request
+    pub fn ok(req: &Request, status: TokenStatus, access_count: u64) -> Response {
+        Response { request_id: req.request_id(), opcode: req.opcode(), status, access_count, err_code: None }
+    }
+
+    /// Error response to a request
+    pub fn error(req: &Request, err: ErrList) -> Response {
+        Response {
+            request_id: req.request_id(),
+            opcode: req.opcode(),
+            status: TokenStatus::Error,
+            access_count: 0,
+            err_code: Some(err.to_code()),
+        }
+    }
+
+    /// Error kind of an error response, if the client knows it
+    pub fn err(&self) -> Option<ErrList> {
+        self.err_code.and_then(ErrList::from_code)
+    }
+}
+
+
+/// Frame header
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Header {
+    version: u8,
+    opcode: u8,
+    request_id: u32,
+    payload_len: usize,
+}
+
+
+impl Header {
+
+    fn put(&self, dst: &mut BytesMut) {
+        dst.reserve(HEADER_LEN + self.payload_len);
+        dst.put_u8(self.version);
+        dst.put_u8(self.opcode);
+        dst.put_u32(self.request_id);
+        dst.put_u32(self.payload_len as u32);
+    }
+}
+
+
+/// Splits the next complete frame off `src`;
+/// returns `Ok(None)` if more data is required.
+fn decode_frame(src: &mut BytesMut) -> Result<Option<(Header, BytesMut)>, AppErr> {
+    if src.len() < HEADER_LEN {
+        src.reserve(HEADER_LEN - src.len());
+        return Ok(None);
+    }
+
+    let mut raw = &src[..HEADER_LEN];
+    let header = Header {
+        version: raw.get_u8(),
+        opcode: raw.get_u8(),
+        request_id: raw.get_u32(),
+        payload_len: raw.get_u32() as usize,
+    };
+
+    if header.payload_len > MAX_PAYLOAD_LEN {
+        return Err(app_err!(ErrList::FrameTooLarge, Some(format!("payload length {}, request id {}",
+                                                                 header.payload_len, header.request_id))));
+    }
+
+    let frame_len = HEADER_LEN + header.payload_len;
+    if src.len() < frame_len {
+        src.reserve(frame_len - src.len());
+        return Ok(None);
+    }
+
+    src.advance(HEADER_LEN);
+    let payload = src.split_to(header.payload_len);
+    Ok(Some((header, payload)))
+}
+
+
+/// Server side codec: decodes requests and encodes responses
+#[derive(Debug, Default)]
+pub struct ServerCodec;
+
+
+impl Decoder for ServerCodec {
+    type Item = Request;
+    type Error = AppErr;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Request>, AppErr> {
+        let (header, payload) = match decode_frame(src)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let invalid = |err| Request::Invalid { request_id: header.request_id, opcode: header.opcode, err };
+
+        if header.version != PROTO_VERSION {
+            return Ok(Some(invalid(ErrList::UnsupportedVersion)));
+        }
+
+        let request_id = header.request_id;
+        let opcode = match Opcode::from_u8(header.opcode) {
+            Some(opcode) => opcode,
+            None => return Ok(Some(invalid(ErrList::UnknownOpcode))),
+        };
+        let req = match opcode {
+            Opcode::CheckToken => match <Token>::try_from(&payload[..]) {
+                Ok(token) => Request::CheckToken { request_id, token },
+                Err(_) => invalid(ErrList::MalformedPayload),
+            },
+        };
+        Ok(Some(req))
+    }
+}
+
+
+impl Encoder<Response> for ServerCodec {
+    type Error = AppErr;
+
+    fn encode(&mut self, resp: Response, dst: &mut BytesMut) -> Result<(), AppErr> {
+        Header {
+            version: PROTO_VERSION,
+            opcode: resp.opcode | RESPONSE_FLAG,
+            request_id: resp.request_id,
+            payload_len: RESPONSE_PAYLOAD_LEN,
+        }.put(dst);
+        dst.put_u8(resp.status as u8);
+        dst.put_u64(resp.access_count);
+        dst.put_u32(resp.err_code.unwrap_or(NO_ERR_CODE));
+        Ok(())
+    }
+}
+
+
+/// Client side codec: encodes requests and decodes responses
+#[derive(Debug, Default)]
+pub struct ClientCodec;
+
+
+impl Decoder for ClientCodec {
+    type Item = Response;
+    type Error = AppErr;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Response>, AppErr> {
+        let (header, mut payload) = match decode_frame(src)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+
+        if header.version != PROTO_VERSION {
+            return Err(app_err!(ErrList::UnsupportedVersion, Some(format!("version {}", header.version))));
+        }
+        if header.opcode & RESPONSE_FLAG == 0 || payload.len() != RESPONSE_PAYLOAD_LEN {
+            return Err(app_err!(ErrList::MalformedPayload, Some(format!("opcode {:#x}, payload length {}",
+                                                                        header.opcode, payload.len()))));
+        }
+
+        let status = payload.get_u8();
+        let status = TokenStatus::from_u8(status)
+            .ok_or_else(|| app_err!(ErrList::MalformedPayload, Some(format!("token status {}", status))))?;
+        let access_count = payload.get_u64();
+        let err_code = match payload.get_u32() {
+            NO_ERR_CODE => None,
+            code => Some(code),
+        };
+
+        Ok(Some(Response {
+            request_id: header.request_id,
+            opcode: header.opcode & !RESPONSE_FLAG,
+            status,
+            access_count,
+            err_code,
+        }))
+    }
+}
+
+
+impl Encoder<Request> for ClientCodec {
+    type Error = AppErr;
+
+    fn encode(&mut self, req: Request, dst: &mut BytesMut) -> Result<(), AppErr> {
+        match req {
+            Request::CheckToken { request_id, token } => {
+                Header { version: PROTO_VERSION, opcode: Opcode::CheckToken as u8, request_id,
+                         payload_len: TOKEN_LEN }.put(dst);
+                dst.put_slice(&token);
+            },
+            // Sent as is, e.g. to test the server
+            Request::Invalid { request_id, opcode, .. } => {
+                Header { version: PROTO_VERSION, opcode, request_id, payload_len: 0 }.put(dst);
+            },
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn request_round_trip() {
+        let mut buf = BytesMut::new();
+        let req = Request::CheckToken { request_id: 7, token: [3; TOKEN_LEN] };
+        ClientCodec.encode(req.clone(), &mut buf).unwrap();
+        ClientCodec.encode(Request::Invalid { request_id: 8, opcode: 0x42, err: ErrList::Other }, &mut buf).unwrap();
+        assert_eq!(buf.len(), 2 * HEADER_LEN + TOKEN_LEN);
+
+        // Frames are decoded only when complete
+        let mut partial = buf.split_to(HEADER_LEN + 3);
+        assert_eq!(ServerCodec.decode(&mut partial).unwrap(), None);
+        partial.unsplit(buf);
+        assert_eq!(ServerCodec.decode(&mut partial).unwrap(), Some(req));
+
+        let unknown = ServerCodec.decode(&mut partial).unwrap().unwrap();
+        assert_eq!(unknown, Request::Invalid { request_id: 8, opcode: 0x42, err: ErrList::UnknownOpcode });
+        assert!(partial.is_empty());
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let req = Request::CheckToken { request_id: 9, token: [0; TOKEN_LEN] };
+        let mut buf = BytesMut::new();
+        ServerCodec.encode(Response::ok(&req, TokenStatus::Valid, 101), &mut buf).unwrap();
+        ServerCodec.encode(Response::error(&req, ErrList::MalformedPayload), &mut buf).unwrap();
+
+        let resp = ClientCodec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!((resp.request_id, resp.status, resp.access_count, resp.err_code), (9, TokenStatus::Valid, 101, None));
+        let resp = ClientCodec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(resp.status, TokenStatus::Error);
+        assert_eq!(resp.err(), Some(ErrList::MalformedPayload));
+    }
+
+    #[test]
+    fn former_admin_opcodes_are_unknown() {
+        let mut buf = BytesMut::new();
+        for opcode in 0x10..0x13 {
+            Header { version: PROTO_VERSION, opcode, request_id: opcode as u32, payload_len: TOKEN_LEN }.put(&mut buf);
+            buf.put_slice(&[5; TOKEN_LEN]);
+            let req = ServerCodec.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(req, Request::Invalid { request_id: opcode as u32, opcode, err: ErrList::UnknownOpcode });
+        }
+    }
+
+    #[test]
+    fn token_hex_round_trip() {
+        let token = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 0xFE, 0xFF];
+        assert_eq!(token_hex(&token), "000102030405060708090a0b0c0dfeff");
+        assert_eq!(parse_token_hex(&token_hex(&token)), Some(token));
+        for invalid in &["0001", "000102030405060708090a0b0c0d0e0g", "000102030405060708090a0b0c0d0e+1"] {
+            assert_eq!(parse_token_hex(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn oversized_frame_is_rejected() {
+        let mut buf = BytesMut::new();
+        Header { version: PROTO_VERSION, opcode: Opcode::CheckToken as u8, request_id: 1,
+                 payload_len: MAX_PAYLOAD_LEN + 1 }.put(&mut buf);
+        let e = ServerCodec.decode(&mut buf).unwrap_err();
+        assert_eq!(e.kind, ErrList::FrameTooLarge);
+    }
+}
@@ -0,0 +1,321 @@
+//! Token store: issued tokens with access counters, expiry time, usage limits and revocation
+
+
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use apptools::app_err;
+use apptools::err::AppResultExt;
+
+use crate::app_err_decl::{AppResult, ErrList};
+use crate::token_proto::{Token, TokenStatus};
+
+
+/// Expiry queue time of revoked and used up tokens, they are removed by the next sweep
+const DEAD_AT: u64 = 0;
+
+
+/// Current time as milliseconds since the Unix epoch, the time unit of the token store
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+
+/// Index of the shard that owns the token, out of `shards`;
+///
+/// FNV-1a hash of the token, stable between restarts, so shard stores remain valid.
+pub fn token_shard(token: &Token, shards: usize) -> usize {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in token {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    (hash % shards as u64) as usize
+}
+
+
+/// Token limits; a token expires when any of them is reached
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct TokenLimits {
+
+    /// Time to live, millis
+    pub ttl_ms: Option<u64>,
+
+    /// Maximum number of successful checks
+    pub max_uses: Option<u64>,
+}
+
+
+/// Random token from the OS cryptographically secure random number generator
+pub fn random_token() -> AppResult<Token> {
+    let mut token = [0_u8; 16];
+    getrandom::getrandom(&mut token).ctx("generating a random token")?;
+    Ok(token)
+}
+
+
+/// Stored token
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TokenEntry {
+
+    /// Number of successful checks
+    pub access_count: u64,
+
+    /// Time the token expires at, millis since the Unix epoch
+    pub expires_at: Option<u64>,
+
+    /// Token expires after this number of successful checks
+    pub max_uses: Option<u64>,
+
+    /// Revoked tokens are kept until the next sweep, so checks report them as revoked meanwhile
+    pub revoked: bool,
+}
+
+
+impl TokenEntry {
+
+    /// Entry of a new token issued at `now`
+    pub fn new(limits: TokenLimits, now: u64) -> TokenEntry {
+        TokenEntry {
+            access_count: 0,
+            expires_at: limits.ttl_ms.map(|ttl| now.saturating_add(ttl)),
+            max_uses: limits.max_uses,
+            revoked: false,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|at| at <= now) || self.is_used_up()
+    }
+
+    pub fn is_used_up(&self) -> bool {
+        self.max_uses.is_some_and(|max| self.access_count >= max)
+    }
+
+    /// Token can never be valid again: revoked or used up
+    pub fn is_dead(&self) -> bool {
+        self.revoked || self.is_used_up()
+    }
+}
+
+
+/// Token store operations; `now` is passed explicitly, see `now_millis()`
+pub trait TokenStore {
+
+    /// Inserts a token with the given limits, fails with `AlreadyExists`
+    fn insert(&mut self, token: Token, limits: TokenLimits, now: u64) -> AppResult<()>;
+
+    /// Inserts a token entry as is (seed data, replay), fails with `AlreadyExists`
+    fn insert_entry(&mut self, token: Token, entry: TokenEntry) -> AppResult<()>;
+
+    /// Generates and inserts a new random token
+    fn issue(&mut self, limits: TokenLimits, now: u64) -> AppResult<Token> {
+        self.issue_where(limits, now, &|_| true)
+    }
+
+    /// Generates and inserts a new random token accepted by `accept`, e.g. owned by a shard;
+    /// fails if the OS random number generator fails
+    fn issue_where(&mut self, limits: TokenLimits, now: u64, accept: &dyn Fn(&Token) -> bool) -> AppResult<Token>;
+
+    /// Revokes a token, fails with `NotFound`
+    fn revoke(&mut self, token: &Token) -> AppResult<()>;
+
+    /// Checks a token and increments its access counter if it is valid;
+    /// returns token status and its access counter.
+    ///
+    /// Expired tokens are removed lazily: the first check after expiry reports `Expired`,
+    /// next checks report `Unknown`.
+    fn check(&mut self, token: &Token, now: u64) -> (TokenStatus, u64);
+
+    /// Removes at most `max` tokens that expired by `now`, revoked or used up;
+    /// returns the number of removed tokens
+    fn sweep(&mut self, now: u64, max: usize) -> usize;
+
+    /// Number of stored tokens, including expired, revoked and used up ones that are not swept yet
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+
+/// In-memory token store
+#[derive(Debug, Default)]
+pub struct MemTokenStore {
+    table: HashMap<Token, TokenEntry>,
+
+    /// Expiry queue, the earliest first, dead tokens at `DEAD_AT`;
+    /// may contain stale items of removed tokens
+    expiry: BinaryHeap<Reverse<(u64, Token)>>,
+}
+
+
+impl MemTokenStore {
+
+    pub fn new() -> MemTokenStore {
+        MemTokenStore::default()
+    }
+
+    /// Iterates over all stored tokens
+    pub fn iter(&self) -> impl Iterator<Item = (&Token, &TokenEntry)> {
+        self.table.iter()
+    }
+}
+
+
+impl TokenStore for MemTokenStore {
+
+    fn insert(&mut self, token: Token, limits: TokenLimits, now: u64) -> AppResult<()> {
+        self.insert_entry(token, TokenEntry::new(limits, now))
+    }
+
+    fn insert_entry(&mut self, token: Token, entry: TokenEntry) -> AppResult<()> {
+        match self.table.entry(token) {
+            Entry::Occupied(_) => Err(app_err!(ErrList::AlreadyExists, Some("token is already stored".to_string()))),
+            Entry::Vacant(vacant) => {
+                vacant.insert(entry);
+                if let Some(at) = entry.expires_at {
+                    self.expiry.push(Reverse((at, token)));
+                }
+                if entry.is_dead() {
+                    self.expiry.push(Reverse((DEAD_AT, token)));
+                }
+                Ok(())
+            },
+        }
+    }
+
+    fn issue_where(&mut self, limits: TokenLimits, now: u64, accept: &dyn Fn(&Token) -> bool) -> AppResult<Token> {
+        loop {
+            let token = random_token()?;
+            if accept(&token) && self.insert(token, limits, now).is_ok() {
+                return Ok(token);
+            }
+        }
+    }
+
+    fn revoke(&mut self, token: &Token) -> AppResult<()> {
+        match self.table.get_mut(token) {
+            Some(entry) => {
+                entry.revoked = true;
+                self.expiry.push(Reverse((DEAD_AT, *token)));
+                Ok(())
+            },
+            None => Err(app_err!(ErrList::NotFound, Some("token is not stored".to_string()))),
+        }
+    }
+
+    fn check(&mut self, token: &Token, now: u64) -> (TokenStatus, u64) {
+        let entry = match self.table.get_mut(token) {
+            Some(entry) => entry,
+            None => return (TokenStatus::Unknown, 0),
+        };
+        if entry.is_expired(now) {
+            let access_count = entry.access_count;
+            self.table.remove(token);
+            return (TokenStatus::Expired, access_count);
+        }
+        if entry.revoked {
+            return (TokenStatus::Revoked, entry.access_count);
+        }
+        entry.access_count += 1;
+        let access_count = entry.access_count;
+        if entry.is_used_up() {
+            self.expiry.push(Reverse((DEAD_AT, *token)));
+        }
+        (TokenStatus::Valid, access_count)
+    }
+
+    fn sweep(&mut self, now: u64, max: usize) -> usize {
+        let mut removed = 0;
+        while removed < max {
+            match self.expiry.peek() {
+                Some(Reverse((at, _))) if *at <= now => {},
+                _ => break,
+            }
+            let Reverse((at, token)) = self.expiry.pop().unwrap();
+            // Skip stale items: the token was removed or re-inserted with another expiry time;
+            // the time of the item is compared, so replaying sweeps over a snapshot removes the same tokens
+            let swept = self.table.get(&token)
+                .is_some_and(|entry| entry.expires_at == Some(at) || (at == DEAD_AT && entry.is_dead()));
+            if swept {
+                self.table.remove(&token);
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    fn len(&self) -> usize {
+        self.table.len()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const TOKEN: Token = [7; 16];
+
+    #[test]
+    fn limits_and_revocation() {
+        let mut store = MemTokenStore::new();
+        store.insert(TOKEN, TokenLimits { ttl_ms: None, max_uses: Some(2) }, 0).unwrap();
+        assert_eq!(store.insert(TOKEN, TokenLimits::default(), 0).unwrap_err().kind, ErrList::AlreadyExists);
+
+        assert_eq!(store.check(&TOKEN, 0), (TokenStatus::Valid, 1));
+        assert_eq!(store.check(&TOKEN, 0), (TokenStatus::Valid, 2));
+        assert_eq!(store.check(&TOKEN, 0), (TokenStatus::Expired, 2));
+        assert_eq!(store.check(&TOKEN, 0), (TokenStatus::Unknown, 0));
+
+        let token = store.issue(TokenLimits::default(), 0).unwrap();
+        assert_ne!(token, store.issue(TokenLimits::default(), 0).unwrap());
+        store.revoke(&token).unwrap();
+        assert_eq!(store.check(&token, 0), (TokenStatus::Revoked, 0));
+        assert_eq!(store.revoke(&TOKEN).unwrap_err().kind, ErrList::NotFound);
+
+        let token = store.issue_where(TokenLimits::default(), 0, &|token| token_shard(token, 4) == 3).unwrap();
+        assert_eq!(token_shard(&token, 4), 3);
+    }
+
+    #[test]
+    fn expiry_sweeping() {
+        let mut store = MemTokenStore::new();
+        for i in 0..10_u8 {
+            store.insert([i; 16], TokenLimits { ttl_ms: Some(100 * i as u64), max_uses: None }, 1000).unwrap();
+        }
+        store.insert([0xAA; 16], TokenLimits::default(), 1000).unwrap();
+
+        assert_eq!(store.check(&[5; 16], 1499), (TokenStatus::Valid, 1));
+        assert_eq!(store.sweep(1500, 2), 2);
+        assert_eq!(store.sweep(1500, 100), 4);
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.check(&[5; 16], 1500), (TokenStatus::Unknown, 0));
+        assert_eq!(store.check(&[6; 16], 1600), (TokenStatus::Expired, 0));
+        assert_eq!(store.sweep(u64::MAX, 100), 3);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn dead_tokens_sweeping() {
+        let mut store = MemTokenStore::new();
+        store.insert(TOKEN, TokenLimits { ttl_ms: None, max_uses: Some(1) }, 0).unwrap();
+        store.insert([1; 16], TokenLimits::default(), 0).unwrap();
+        store.insert([2; 16], TokenLimits::default(), 0).unwrap();
+        let revoked_entry = TokenEntry { access_count: 0, expires_at: None, max_uses: None, revoked: true };
+        store.insert_entry([3; 16], revoked_entry).unwrap();
+
+        assert_eq!(store.check(&TOKEN, 0), (TokenStatus::Valid, 1));
+        store.revoke(&[1; 16]).unwrap();
+        assert_eq!(store.check(&[1; 16], 0), (TokenStatus::Revoked, 0));
+        assert_eq!(store.sweep(0, 100), 3);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.check(&TOKEN, 0), (TokenStatus::Unknown, 0));
+        assert_eq!(store.check(&[2; 16], 0), (TokenStatus::Valid, 1));
+    }
+}
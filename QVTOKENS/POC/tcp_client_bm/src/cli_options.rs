@@ -24,6 +24,9 @@ pub struct CliOpts {
     /// or session_duration exceeded.
     pub requests_to_do: u32,
 
+    /// Number of requests sent over a connection before reading their responses
+    pub pipeline: u32,
+
 }
 
 
@@ -36,6 +39,7 @@ impl CliOpts {
             threads: 0,
             session_duration: Duration::from_secs(10),
             requests_to_do: 10,
+            pipeline: 1,
         }
     }
 
@@ -58,6 +62,10 @@ impl CliOpts {
             self.requests_to_do = r.parse::<u32>().unwrap();
         }
 
+        if let Some(p) = matches.value_of("pipeline") {
+            self.pipeline = p.parse::<u32>().unwrap().max(1);
+        }
+
     }
 
 }
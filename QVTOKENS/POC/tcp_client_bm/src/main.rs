@@ -39,7 +39,8 @@ fn main() {
         .arg("-c, --connections=[INT] 'total number of parallel TCP connections to keep open with each thread handling N = connections/threads'")
         .arg("-d, --duration=[INT] 'test session duration in seconds'")
         .arg("-t, --threads=[INT] 'total number of threads to use; 0 (default) - number of threads corresponds to number of CPU cores'")
-        .arg("-r, --requests=[INT] 'total number of requests to do'")
+        .arg("-r, --requests=[INT] 'number of requests to do over each connection'")
+        .arg("-p, --pipeline=[INT] 'number of requests sent before reading their responses (default 1)'")
         .get_matches();
 
     opts.parse(&matches);
@@ -9,14 +9,15 @@ use tokio::runtime::Builder;
 use tokio::time::{Duration};  // self, Duration
 
 use tokio::net::{TcpStream };  // TcpListener, 
-//use tokio_util::codec::{ Framed, LinesCodec };
-//use tokio::stream::StreamExt;
-//use std::error::Error;
+use tokio_util::codec::Framed;
+use futures::{SinkExt, StreamExt};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::collections::HashSet;
 
 use log::*;
 
+use poc1_tokio_playground::token_proto::{ClientCodec, Request, TokenStatus};
+
 // const SRV_IP: (u8, u8, u8, u8) = (127, 0, 0, 1);
 const SRV_IP: (u8, u8, u8, u8) = (192, 168, 1, 201);
 
@@ -26,7 +27,7 @@ pub fn run(opts: &CliOpts) {
         0 => {
             info!("* Running multi-client with default tokio runtime.");
             let rt = tokio::runtime::Runtime::new().unwrap();
-            rt.block_on(run_server(&opts));
+            rt.block_on(run_server(opts));
         }
 
         _ => {
@@ -38,7 +39,7 @@ pub fn run(opts: &CliOpts) {
                 .build()
                 .unwrap();
 
-            rt.block_on(run_server(&opts));
+            rt.block_on(run_server(opts));
             rt.shutdown_timeout(Duration::from_millis(2000));
         }
     }
@@ -77,7 +78,8 @@ async fn run_server(opts: & CliOpts) {
     for conn_id in 0..opts.parallel_conns {
 
 
-        let parallel_conns = opts.parallel_conns;
+        let requests_to_do = opts.requests_to_do;
+        let pipeline = opts.pipeline;
 
         // let mut conn = TcpStream::connect(addr).await
         //             .map_err(|e| { trace!("  -> couldn't connect to server, {:?}", e); })
@@ -85,7 +87,7 @@ async fn run_server(opts: & CliOpts) {
 
         // let mut joined_task_handles = vec!();
 
-        let mut conn = match TcpStream::connect(addr).await {
+        let conn = match TcpStream::connect(addr).await {
             Ok(val) => val,
             Err(e) => { warn!("  -> couldn't connect to server, {:?}", e); return; }
         };
@@ -97,39 +99,56 @@ async fn run_server(opts: & CliOpts) {
         // Under the hood, they require only a single allocation and 64 bytes of memory.
         tokio::spawn(async move {  // let h = 
 
-            let mut buf: [u8; 16] = [0; 16];
-
-            if conn_id == parallel_conns - 1 {
-                buf = [0xFF; 16];
-            }
+            let mut conn = Framed::new(conn, ClientCodec);
+            let token = [0_u8; 16];
             trace!("  * Conn #: {}", &conn_id);
 
-            let mut req_count = 0;
+            let mut req_count: u32 = 0;
+            let mut in_flight = HashSet::new();
 
-            // Read socket data in a loop
-            loop {
+            // Check the token and verify responses in a loop
+            while req_count < requests_to_do {
 
-                // Write the token
-                if let Err(e) = conn.write_all(&buf).await {
-                    eprintln!("  -> (conn #{}) failed to write to socket; err = {:?}", 
-                              conn_id, e);
+                // Write a batch of pipelined requests
+                let batch = pipeline.min(requests_to_do - req_count);
+                for request_id in req_count..req_count + batch {
+                    in_flight.insert(request_id);
+                    if let Err(e) = conn.feed(Request::CheckToken { request_id, token }).await {
+                        eprintln!("  -> (conn #{}) failed to write to socket; err = {}", conn_id, e);
+                        return;
+                    }
+                }
+                if let Err(e) = conn.flush().await {
+                    eprintln!("  -> (conn #{}) failed to write to socket; err = {}", conn_id, e);
                     return;
                 }
-
-                req_count += 1;
-
-                let n = match conn.read(&mut buf).await {
-                    // socket closed
-                    Ok(n) if n == 0 => return,
-                    Ok(n) => n,
-                    Err(e) => {
-                        eprintln!("failed to read from socket; err = {:?}", e);
+                req_count += batch;
+
+                // Match responses by request id
+                while !in_flight.is_empty() {
+                    let resp = match conn.next().await {
+                        // socket closed
+                        None => return,
+                        Some(Ok(resp)) => resp,
+                        Some(Err(e)) => {
+                            eprintln!("failed to read response; err = {}", e);
+                            return;
+                        }
+                    };
+
+                    if !in_flight.remove(&resp.request_id) {
+                        eprintln!("  -> (conn #{}) unexpected response to request #{}", conn_id, resp.request_id);
+                        return;
+                    }
+                    if resp.status == TokenStatus::Error {
+                        eprintln!("  -> (conn #{}) request #{} failed, error: {:?} (code {:?})",
+                                  conn_id, resp.request_id, resp.err(), resp.err_code);
                         return;
                     }
-                };
 
-                println!("  * (conn #{}) received request #{}, {} byte(s).", &conn_id, &req_count, n);
-                
+                    println!("  * (conn #{}) request #{}: token {:?}, access counter {}.",
+                             &conn_id, &resp.request_id, resp.status, resp.access_count);
+                }
             }
         });
 
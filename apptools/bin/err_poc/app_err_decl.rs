@@ -1,9 +1,53 @@
 
 // Declare custom app errors
 
+use apptools::AppErrorKind;
 
-apptools::declare_app_errors!(ErrList, ErrListLookupTable,
-    UserHasNoProfile, "user has no profile",
-    RequestLimitExceeded, "user exceeded the number of requests per time unit",
-    DummyError, "a dummy error"
-);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, AppErrorKind)]
+#[app_err(desc_table = ErrListLookupTable)]
+pub enum ErrList {
+
+    // Built-in kinds: codes, descriptions and metadata are predefined by apptools
+    NotFound,
+    PermissionDenied,
+    ConnectionRefused,
+    ConnectionReset,
+    ConnectionAborted,
+    NotConnected,
+    AddrInUse,
+    AddrNotAvailable,
+    BrokenPipe,
+    AlreadyExists,
+    WouldBlock,
+    InvalidInput,
+    InvalidData,
+    TimedOut,
+    WriteZero,
+    Interrupted,
+    Other,
+    UnexpectedEof,
+    FromOtherError,
+    Panicked,
+
+    // User-defined kinds
+
+    /// user has no profile
+    UserHasNoProfile,
+
+    /// Too many requests from a single user, the client should back off and retry
+    #[desc = "user exceeded the number of requests per time unit"]
+    #[app_err(code = 1100, category = Resource, severity = Warning, retryable)]
+    RequestLimitExceeded,
+
+    /// a dummy error
+    DummyError,
+}
+
+
+// Bind generic apptools error types to this app's error kind enum
+pub type AppErr = apptools::err::AppErr<ErrList>;
+pub type LwErr = apptools::err::LwErr<ErrList>;
+pub type AppErrs = apptools::err::AppErrs<ErrList>;
+pub type AppResult<T> = apptools::err::AppResult<T, ErrList>;
+pub type LwResult<T> = apptools::err::LwResult<T, ErrList>;
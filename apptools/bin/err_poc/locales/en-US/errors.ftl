@@ -0,0 +1,7 @@
+# Error kind descriptions, message IDs are derived from error kind names:
+# `NotFound` -> `err-not-found`; missing messages fall back to the built-in English table.
+err-not-found = an entity (possibly a file) could not be found
+err-permission-denied = operation lacked necessary privileges to complete
+err-timed-out = I/O operation's timeout expired, causing it to be canceled
+err-user-has-no-profile = user has no profile
+err-request-limit-exceeded = user exceeded the number of requests per time unit
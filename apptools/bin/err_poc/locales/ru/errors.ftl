@@ -0,0 +1,4 @@
+err-not-found = объект (возможно, файл) не найден
+err-permission-denied = для выполнения операции недостаточно прав доступа
+err-timed-out = время ожидания операции ввода-вывода истекло, операция отменена
+err-user-has-no-profile = у пользователя нет профиля
@@ -0,0 +1,5 @@
+err-not-found = об'єкт (можливо, файл) не знайдено
+err-permission-denied = для виконання операції бракує прав доступу
+err-timed-out = час очікування операції вводу-виводу вичерпано, операцію скасовано
+err-user-has-no-profile = користувач не має профілю
+err-request-limit-exceeded = користувач перевищив кількість запитів за одиницю часу
@@ -2,9 +2,11 @@
 #[allow(unused)]
 // use apptools;
 use apptools::{app_err, neg_result, app_err_from_std, app_err_from_other, succ};
+use apptools::{lw_err, lw_neg_result, succ_lw};
+use apptools::err::{app_err_desc, ErrKind, AppResultExt, collect_errors};
 
 mod app_err_decl;
-use app_err_decl::{AppErr, AppResult, LwErr, ErrList, app_err_desc};
+use app_err_decl::{AppErr, AppErrs, AppResult, LwErr, LwResult, ErrList};
 
 
 #[allow(unused)]
@@ -13,7 +15,9 @@ fn print_type_of<T>(_: &T) {
 }
 
 
+use std::convert::TryFrom;
 use std::fs::File;
+use std::panic::Location;
 
 
 fn basic_usage_demo() {
@@ -29,13 +33,14 @@ fn basic_usage_demo() {
     println!(" * Size of Option<String>: {} bytes", std::mem::size_of::<Option<String>>());
 
     println!(" * Size of Vec<usize>: {} bytes", std::mem::size_of::<Vec<usize>>());
+    println!(" * Size of CodeTrace: {} bytes", std::mem::size_of::<apptools::err::CodeTrace>());
     // println!(" * Size of Option<String>: {} bytes", std::mem::size_of::<Option<String>>());
 
 
-    let e = AppErr::new(ErrList::UserHasNoProfile, "line 1", None);
+    let e = AppErr::new(ErrList::UserHasNoProfile, Location::caller(), None);
     println!(" * Error (intended) - {:?} at {}: {}.", &(e.kind), &(e.at), app_err_desc(&(e.kind)));
 
-    let e = AppErr::new(ErrList::NotFound, "line 2", Some("file 'dummy.txt'".to_owned()));
+    let e = AppErr::new(ErrList::NotFound, Location::caller(), Some("file 'dummy.txt'".to_owned()));
     println!(" * Error (intended) - {:?} at {}: {}.", &(e.kind), &(e.at), app_err_desc(&(e.kind)));
 
     let e = app_err!(ErrList::PermissionDenied, None);  // Some("file 'dummy.txt'")
@@ -50,7 +55,7 @@ fn basic_usage_demo() {
     match reader {
         Ok(_) => println!(" * file '{}' opened successfully.", filename),
         Err(source) => {
-            let e = app_err_from_std!(Some(format!("failed to open file '{}'", filename)), source);
+            let e: AppErr = app_err_from_std!(Some(format!("failed to open file '{}'", filename)), source);
             // let e = app_err_from_other!(ErrList::NotFound, Some(format!("failed to open file '{}'", filename)), source);
             println!(" * Error (intended) - {:?}.", e);  // :?
         },
@@ -66,14 +71,35 @@ fn basic_usage_demo() {
 }
 
 
+fn error_chain_demo() {
+
+    let filename = "not_exists.txt";
+    let source = match File::open(filename) {
+        Ok(_) => return,
+        Err(e) => e,
+    };
+
+    let inner: AppErr = app_err_from_std!(Some(format!("failed to open file '{}'", filename)), source);
+    let outer = app_err_from_other!(ErrList::UserHasNoProfile, Some("profile can't be loaded".to_owned()), inner);
+
+    // Alternate formatting prints the whole causal chain
+    println!(" * Error (intended) - {:#}.", &outer);
+
+    for (i, cause) in outer.chain().enumerate() {
+        println!("   {}: {}", i, cause);
+    }
+    println!(" * Root cause: {}", outer.root_cause());
+}
+
+
 fn dummy_func_returning_app_result(produce_error: bool) -> AppResult<&'static str> {
 
-    static DUMMY_MSG: &'static str = "dummy message";
+    static DUMMY_MSG: &str = "dummy message";
     if !produce_error {
         return Ok(DUMMY_MSG);
     }
 
-    neg_result!(ErrList::DummyError, Some(format!("produced on purpose")))
+    neg_result!(ErrList::DummyError, Some("produced on purpose".to_owned()))
 }
 
 
@@ -90,18 +116,263 @@ fn call_dummy_func() -> AppResult<()> {
 }
 
 
+/// Foreign errors are converted into AppErr by plain `?`
+fn parse_port(s: &str) -> AppResult<u16> {
+    let port = s.trim().parse::<u16>()?;
+    Ok(port)
+}
+
+
+fn read_port_from_file(filename: &str) -> AppResult<u16> {
+    let s = std::fs::read_to_string(filename)?;
+    let port = succ!(parse_port(&s));
+    Ok(port)
+}
+
+
+fn question_mark_demo() {
+
+    match parse_port("80a") {
+        Ok(port) => println!(" * Parsed port: {}", port),
+        Err(e) => println!(" * Error (intended) - {:#}", &e),
+    }
+
+    match read_port_from_file("not_exists.txt") {
+        Ok(port) => println!(" * Port read from file: {}", port),
+        Err(e) => println!(" * Error (intended) - {:#}", &e),
+    }
+}
+
+
+/// Context is attached to foreign errors and empty options, error kind is inferred by `?`
+fn find_user_port(user: &str) -> AppResult<u16> {
+    let profiles = [("alice", "8080"), ("bob", "80b")];
+    let (_, port) = profiles.iter().find(|(name, _)| *name == user).or_app_err(ErrList::UserHasNoProfile)?;
+    let port = port.parse::<u16>().with_ctx(|| format!("bad port in the profile of '{}'", user))?;
+    Ok(port)
+}
+
+
+fn context_demo() {
+
+    for user in &["alice", "bob", "carol"] {
+        match find_user_port(user) {
+            Ok(port) => println!(" * Port of '{}': {}", user, port),
+            Err(e) => println!(" * Error (intended) - {:#}", &e),
+        }
+    }
+
+    let e: AppErr = parse_port("x").kind(ErrList::InvalidInput).unwrap_err();
+    println!(" * Error (intended) with kind set by caller - {}", &e);
+}
+
+
+/// Batch validation reports every failure, not just the first one
+fn parse_ports(items: &[&str]) -> Result<Vec<u16>, AppErrs> {
+    let ports = succ!(collect_errors(items.iter().map(|s| parse_port(s))));
+    Ok(ports)
+}
+
+
+fn parse_port_list(list: &str) -> AppResult<Vec<u16>> {
+    let items: Vec<&str> = list.split(',').collect();
+    let ports = succ!(parse_ports(&items));
+    Ok(ports)
+}
+
+
+fn multi_error_demo() {
+
+    match parse_ports(&["80", "8o", "443", "", "70000"]) {
+        Ok(ports) => println!(" * Parsed ports: {:?}", ports),
+        Err(errs) => println!(" * Errors (intended) - {:#}", &errs),
+    }
+
+    match parse_port_list("80,x") {
+        Ok(ports) => println!(" * Parsed ports: {:?}", ports),
+        Err(e) => println!(" * Error (intended) - {}", &e),
+    }
+}
+
+
+/// Hot path: light-weight errors are propagated by plain `?` without allocation
+fn check_request_quota(requests: &[u32], limit: u32) -> LwResult<u32> {
+    let mut total = 0;
+    for r in requests {
+        total += r;
+        if total > limit {
+            return lw_neg_result!(ErrList::RequestLimitExceeded);
+        }
+    }
+    Ok(total)
+}
+
+
+fn lw_err_demo() -> AppResult<()> {
+
+    let e: LwErr = lw_err!(ErrList::WouldBlock);
+    println!(" * Light-weight error (intended) - {}", &e);
+
+    let total = succ_lw!(check_request_quota(&[1, 2, 3], 10));
+    println!(" * Requests total: {}", total);
+
+    // LwErr is converted into AppErr at the boundary
+    let total = succ_lw!(check_request_quota(&[5, 6, 7], 10));
+    println!(" * Requests total: {}", total);
+    Ok(())
+}
+
+
+fn error_codes_demo() {
+
+    // Codes are stable, so they can be sent to another process and decoded there
+    for kind in &[ErrList::NotFound, ErrList::FromOtherError, ErrList::UserHasNoProfile,
+                  ErrList::RequestLimitExceeded, ErrList::DummyError] {
+        let code = kind.to_code();
+        println!(" * {:?} <-> code {} <-> {:?}", kind, code, ErrList::try_from(code));
+    }
+    println!(" * Decoding unknown code: {:?}", ErrList::try_from(999));
+}
+
+
+#[cfg(feature = "fluent")]
+fluent_templates::static_loader! {
+    static ERR_LOC = {
+        locales: "./bin/err_poc/locales",
+        fallback_language: "en-US",
+        customise: |bundle| bundle.set_use_isolating(false),
+    };
+}
+
+
+#[cfg(feature = "fluent")]
+fn localized_errors_demo() {
+    use apptools::err::{LanguageIdentifier, app_err_desc_lang};
+    use unic_langid::langid;
+
+    const ENG: LanguageIdentifier = langid!("en-US");
+    const RUS: LanguageIdentifier = langid!("ru");
+    const UKR: LanguageIdentifier = langid!("uk");
+
+    // `ru` has no translation for RequestLimitExceeded (falls back to en-US bundle),
+    // DummyError is not translated at all (falls back to the built-in table)
+    for lang in &[ENG, RUS, UKR] {
+        for kind in &[ErrList::NotFound, ErrList::RequestLimitExceeded, ErrList::DummyError] {
+            println!(" * [{}] {:?}: {}", lang, kind, app_err_desc_lang(kind, &*ERR_LOC, lang));
+        }
+    }
+}
+
+
+/// Fails with a retryable error `failures` times, then succeeds
+fn flaky_request(attempt: u32, failures: u32) -> AppResult<u32> {
+    if attempt < failures {
+        return neg_result!(ErrList::RequestLimitExceeded, Some(format!("attempt {}", attempt)));
+    }
+    if attempt > 10 {
+        return neg_result!(ErrList::UserHasNoProfile, None);
+    }
+    Ok(attempt)
+}
+
+
+fn retry_demo() {
+
+    for kind in &[ErrList::TimedOut, ErrList::NotFound, ErrList::RequestLimitExceeded] {
+        println!(" * {:?}: category {:?}, severity {:?}, retryable: {}",
+                 kind, kind.category(), kind.severity(), kind.is_retryable());
+    }
+
+    let mut attempt = 0;
+    let result = loop {
+        match flaky_request(attempt, 3) {
+            Err(e) if e.is_retryable() => {
+                println!(" * Retrying after error (intended) - {}", &e);
+                attempt += 1;
+            }
+            other => break other,
+        }
+    };
+    println!(" * Result after {} retries: {:?}", attempt, result.map_err(|e| e.brief()));
+}
+
+
+fn cross_thread_demo() {
+
+    // AppErr is Send + Sync, so AppResult can be returned from another thread (or a tokio task)
+    let handle = std::thread::spawn(|| -> AppResult<()> {
+        call_dummy_func()
+    });
+
+    match handle.join() {
+        Ok(Err(e)) => println!(" * Error (intended) returned from spawned thread: {}", &e),
+        Ok(Ok(_)) => println!(" * Spawned thread succeeded"),
+        Err(_) => println!(" * Spawned thread panicked"),
+    }
+}
+
+
+fn panic_demo() {
+
+    // Each panic is also reported as AppErr; pass `apptools::err::default_crash_report_path()`
+    // to write a JSON crash report next to the binary
+    apptools::err::install_panic_hook::<ErrList, _>(None, |e| {
+        println!(" * Panic (intended) reported as: {:#}", e);
+    });
+
+    let handle = std::thread::Builder::new().name("worker".into()).spawn(|| {
+        let active_conns: Vec<u32> = vec![1];
+        assert!(active_conns.is_empty(), "{} active connections left", active_conns.len());
+    }).unwrap();
+    let _ = handle.join();
+
+    let _ = std::panic::take_hook();  // restore the default hook
+}
+
+
 pub fn main() {
 
     println!("== apptools::err proof of concept app begin ==");
 
+    // Count all errors created by this app
+    let metrics = apptools::err::install_err_metrics();
+
     basic_usage_demo();
 
+    error_chain_demo();
+
     // This is the highest level of this app's error processing logic, so it must process all errors
 
     let _ = call_dummy_func().map_err(|e| { 
-        println!("  * Error (intended) while executing 'call_dummy_func()':  {}", &e)
+        println!("  * Error (intended) while executing 'call_dummy_func()':  {}", &e);
+        println!("  * Propagation trace:\n{}", e.at.multi_line("    "));
     });
     
 
+    question_mark_demo();
+
+    context_demo();
+
+    multi_error_demo();
+
+    if let Err(e) = lw_err_demo() {
+        println!(" * Error (intended) while executing 'lw_err_demo()': {}", &e);
+    }
+
+    error_codes_demo();
+
+    #[cfg(feature = "fluent")]
+    localized_errors_demo();
+
+    retry_demo();
+
+    cross_thread_demo();
+
+    panic_demo();
+
+    let snapshot = metrics.snapshot::<ErrList>(3);
+    println!(" * Errors created: {}, by kind: {:?}", snapshot.total, snapshot.by_kind);
+    print!("{}", snapshot.to_prometheus("err_poc"));
+
     println!("== apptools::err proof of concept app end ==");
 }
@@ -0,0 +1,419 @@
+//! `#[derive(AppErrorKind)]`: implements `apptools::err::ErrKind` for a plain enum.
+//!
+//! ```ignore
+//! #[derive(Debug, Copy, Clone, PartialEq, Eq, AppErrorKind)]
+//! #[app_err(desc_table = ErrListLookupTable)]
+//! pub enum ErrList {
+//!     NotFound,  // built-in kind: code, description and metadata are predefined
+//!     Other,
+//!     FromOtherError,
+//!
+//!     /// user has no profile
+//!     UserHasNoProfile,
+//!
+//!     #[desc = "user exceeded the number of requests per time unit"]
+//!     #[app_err(code = 1100, category = Resource, severity = Warning, retryable)]
+//!     RequestLimitExceeded,
+//! }
+//! ```
+//!
+//! Enum attributes `#[app_err(...)]`:
+//!   * `std_kinds = false` - variants named after `std::io::ErrorKind` are not treated as built-in kinds,
+//!     all io errors become the `from_other` kind;
+//!   * `desc_table = Ident` - also generate the description lookup table constant.
+//!
+//! Variant attributes:
+//!   * `#[desc = "..."]` - description, defaults to the doc comment (required for non built-in kinds);
+//!   * `#[app_err(code = N)]` - pin the numeric code, next kinds continue from `N + 1`;
+//!     required on `#[cfg]`-gated kinds and on every kind after them, so codes don't depend on enabled features;
+//!   * `#[app_err(category = C, severity = S, retryable)]` - metadata, see `ErrCategory` and `ErrSeverity`;
+//!   * `#[app_err(from_other)]` - the kind used for foreign errors (defaults to the `FromOtherError` variant).
+//!
+//! Codes can't be set with enum discriminants (`Foo = 5`), use `#[app_err(code = N)]`.
+//!
+//! Panics are reported as the built-in `Panicked` kind if the enum has one, otherwise as the `from_other` kind.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Expr, ExprLit, Fields, Ident, Lit, LitStr, Meta};
+
+
+// The built-in error kinds table and `USER_ERR_CODE_BASE` are shared with apptools,
+// which this crate can't depend on
+#[macro_use]
+mod builtin_kinds {
+    include!("../../src/err/builtin_kinds.rs");
+}
+use builtin_kinds::USER_ERR_CODE_BASE;
+
+macro_rules! builtin_kind_names {
+    ( io: [ $( $Io:ident = $IoCode:expr, $IoDesc:expr => { $($IoMeta:tt)* }, )* ],
+      other: [ $( $Other:ident = $OtherCode:expr, $OtherDesc:expr => { $($OtherMeta:tt)* }, )* ], ) => {
+
+        /// Built-in kind names, the index is the stable error code (see `apptools::err::BUILTIN_KINDS`)
+        const BUILTIN_KIND_NAMES: &[&str] = &[ $( stringify!($Io), )* $( stringify!($Other), )* ];
+
+        /// Number of leading built-in kinds that are `std::io::ErrorKind` counterparts
+        const IO_KINDS_CNT: usize = [ $( stringify!($Io), )* ].len();
+    };
+}
+
+__builtin_err_kinds!([builtin_kind_names]);
+
+
+#[proc_macro_derive(AppErrorKind, attributes(desc, app_err))]
+pub fn derive_app_error_kind(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+
+/// Parsed enum variant
+struct Kind {
+    ident: Ident,
+    code: u32,
+
+    /// Index in `BUILTIN_KINDS` if this is a built-in kind
+    builtin: Option<usize>,
+    desc: Option<LitStr>,
+    category: Option<Ident>,
+    severity: Option<Ident>,
+    retryable: Option<bool>,
+    from_other: bool,
+}
+
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => return Err(syn::Error::new_spanned(&input.ident, "AppErrorKind can only be derived for enums")),
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&input.generics, "AppErrorKind enums can't be generic"));
+    }
+
+    // Enum attributes
+    let mut std_kinds = true;
+    let mut desc_table: Option<Ident> = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("app_err")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("std_kinds") {
+                std_kinds = meta.value()?.parse::<syn::LitBool>()?.value;
+                Ok(())
+            } else if meta.path.is_ident("desc_table") {
+                desc_table = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unknown enum attribute, expected `std_kinds` or `desc_table`"))
+            }
+        })?;
+    }
+
+    // Variants
+    let mut kinds = Vec::new();
+    let mut next_user_code = USER_ERR_CODE_BASE;
+
+    // First cfg-gated user kind: it's invisible when disabled, so codes after it must be pinned
+    let mut first_gated: Option<&Ident> = None;
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new_spanned(variant,
+                "AppErrorKind variants can't have fields: error kinds must be `Copy` and decodable from a numeric code"));
+        }
+        if let Some((_, discriminant)) = &variant.discriminant {
+            return Err(syn::Error::new_spanned(discriminant,
+                "AppErrorKind variants can't have discriminants, pin the code with `#[app_err(code = N)]`"));
+        }
+        let gated = variant.attrs.iter().any(|a| a.path().is_ident("cfg"));
+
+        let builtin = if std_kinds {
+            BUILTIN_KIND_NAMES.iter().position(|name| variant.ident == name)
+        } else {
+            None
+        };
+
+        let mut kind = Kind {
+            ident: variant.ident.clone(),
+            code: 0,
+            builtin,
+            desc: None,
+            category: None,
+            severity: None,
+            retryable: None,
+            from_other: false,
+        };
+        let mut pinned_code = None;
+        let mut doc = Vec::new();
+
+        for attr in &variant.attrs {
+            if attr.path().is_ident("desc") {
+                match &attr.meta {
+                    Meta::NameValue(nv) => match &nv.value {
+                        Expr::Lit(ExprLit { lit: Lit::Str(s), .. }) => kind.desc = Some(s.clone()),
+                        other => return Err(syn::Error::new_spanned(other, "expected `#[desc = \"...\"]`")),
+                    },
+                    other => return Err(syn::Error::new_spanned(other, "expected `#[desc = \"...\"]`")),
+                }
+            } else if attr.path().is_ident("doc") {
+                if let Meta::NameValue(nv) = &attr.meta {
+                    if let Expr::Lit(ExprLit { lit: Lit::Str(s), .. }) = &nv.value {
+                        doc.push(s.value().trim().to_owned());
+                    }
+                }
+            } else if attr.path().is_ident("app_err") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("code") {
+                        pinned_code = Some((meta.value()?.parse::<syn::LitInt>()?.base10_parse::<u32>()?, meta.path.clone()));
+                    } else if meta.path.is_ident("category") {
+                        kind.category = Some(meta.value()?.parse()?);
+                    } else if meta.path.is_ident("severity") {
+                        kind.severity = Some(meta.value()?.parse()?);
+                    } else if meta.path.is_ident("retryable") {
+                        kind.retryable = Some(if meta.input.peek(syn::Token![=]) {
+                            meta.value()?.parse::<syn::LitBool>()?.value
+                        } else {
+                            true
+                        });
+                    } else if meta.path.is_ident("from_other") {
+                        kind.from_other = true;
+                    } else {
+                        return Err(meta.error(
+                            "unknown variant attribute, expected `code`, `category`, `severity`, `retryable` or `from_other`"));
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+
+        if kind.desc.is_none() && !doc.is_empty() {
+            kind.desc = Some(LitStr::new(doc.join(" ").trim(), variant.ident.span()));
+        }
+
+        kind.code = match (pinned_code, kind.builtin) {
+            (Some((_, path)), Some(_)) => {
+                return Err(syn::Error::new_spanned(path, "codes of built-in error kinds can't be changed"));
+            }
+            (None, Some(i)) => i as u32,
+            (Some((code, path)), None) => {
+                if code < USER_ERR_CODE_BASE {
+                    return Err(syn::Error::new_spanned(path,
+                        format!("codes below {} are reserved for built-in error kinds", USER_ERR_CODE_BASE)));
+                }
+                next_user_code = code.wrapping_add(1);
+                code
+            }
+            (None, None) if gated => {
+                return Err(syn::Error::new_spanned(&variant.ident,
+                    "cfg-gated error kinds must pin their code with `#[app_err(code = N)]`"));
+            }
+            (None, None) => {
+                if let Some(gated) = first_gated {
+                    return Err(syn::Error::new_spanned(&variant.ident, format!(
+                        "pin the code with `#[app_err(code = N)]`: it would change when the cfg-gated `{}` is disabled",
+                        gated)));
+                }
+                let code = next_user_code;
+                next_user_code += 1;
+                code
+            }
+        };
+
+        if gated && kind.builtin.is_none() && first_gated.is_none() {
+            first_gated = Some(&variant.ident);
+        }
+
+        if kind.builtin.is_none() && kind.desc.is_none() {
+            return Err(syn::Error::new_spanned(&variant.ident,
+                "missing description: add `#[desc = \"...\"]` or a doc comment"));
+        }
+
+        kinds.push(kind);
+    }
+
+    // Check for duplicate codes
+    for (i, a) in kinds.iter().enumerate() {
+        if let Some(b) = kinds[..i].iter().find(|b| b.code == a.code) {
+            return Err(syn::Error::new_spanned(&a.ident,
+                format!("error code {} is already used by `{}`", a.code, b.ident)));
+        }
+    }
+
+    // Kind used for foreign errors
+    let from_other = match kinds.iter().filter(|k| k.from_other).count() {
+        0 => kinds.iter().find(|k| k.ident == "FromOtherError"),
+        1 => kinds.iter().find(|k| k.from_other),
+        _ => return Err(syn::Error::new_spanned(&input.ident, "only one variant can be marked `#[app_err(from_other)]`")),
+    };
+    let from_other = match from_other {
+        Some(k) => &k.ident,
+        None => return Err(syn::Error::new_spanned(&input.ident,
+            "add a `FromOtherError` variant or mark a variant with `#[app_err(from_other)]`")),
+    };
+
+    Ok(generate(input, &kinds, from_other, desc_table))
+}
+
+
+fn generate(input: &DeriveInput, kinds: &[Kind], from_other: &Ident, desc_table: Option<Ident>) -> TokenStream2 {
+
+    let enum_ident = &input.ident;
+    let apptools = quote!(::apptools);
+    let idents: Vec<&Ident> = kinds.iter().map(|k| &k.ident).collect();
+    let codes: Vec<u32> = kinds.iter().map(|k| k.code).collect();
+    let names: Vec<String> = kinds.iter().map(|k| k.ident.to_string()).collect();
+
+    let descs: Vec<TokenStream2> = kinds.iter().map(|k| match (&k.desc, k.builtin) {
+        (Some(desc), _) => quote!(#desc),
+        (None, Some(i)) => quote!(#apptools::err::BUILTIN_KINDS[#i].desc),
+        (None, None) => unreachable!(),
+    }).collect();
+
+    let categories: Vec<TokenStream2> = kinds.iter().map(|k| match (&k.category, k.builtin) {
+        (Some(c), _) => quote!(#apptools::err::ErrCategory::#c),
+        (None, Some(i)) => quote!(#apptools::err::BUILTIN_KINDS[#i].category),
+        (None, None) => quote!(#apptools::err::ErrCategory::General),
+    }).collect();
+
+    let severities: Vec<TokenStream2> = kinds.iter().map(|k| match (&k.severity, k.builtin) {
+        (Some(s), _) => quote!(#apptools::err::ErrSeverity::#s),
+        (None, Some(i)) => quote!(#apptools::err::BUILTIN_KINDS[#i].severity),
+        (None, None) => quote!(#apptools::err::ErrSeverity::Error),
+    }).collect();
+
+    let retryables: Vec<TokenStream2> = kinds.iter().map(|k| match (k.retryable, k.builtin) {
+        (Some(r), _) => quote!(#r),
+        (None, Some(i)) => quote!(#apptools::err::BUILTIN_KINDS[#i].retryable),
+        (None, None) => quote!(false),
+    }).collect();
+
+    // std::io::ErrorKind translation
+    let io_kinds: Vec<&Ident> = kinds.iter()
+        .filter(|k| matches!(k.builtin, Some(i) if i < IO_KINDS_CNT))
+        .map(|k| &k.ident)
+        .collect();
+    let io_fallback = kinds.iter()
+        .find(|k| k.builtin.is_some() && k.ident == "Other")
+        .map(|k| &k.ident)
+        .unwrap_or(from_other);
+    let panicked = kinds.iter()
+        .find(|k| k.builtin.is_some() && k.ident == "Panicked")
+        .map(|k| &k.ident)
+        .unwrap_or(from_other);
+
+    let desc_table = desc_table.map(|table| quote! {
+        /// Error description table (exactly matches the enum order)
+        #[allow(unused)]
+        #[allow(non_upper_case_globals)]
+        pub const #table: &[&str] = &[ #( #descs, )* ];
+
+        // The description table must describe every error kind
+        const _: () = assert!(#table.len() == #enum_ident::ALL.len(),
+                              "error description table and error enum lengths differ");
+    });
+
+    quote! {
+        #desc_table
+
+        #[allow(unused)]
+        impl #enum_ident {
+
+            /// All error kinds in declaration order
+            pub const ALL: &'static [#enum_ident] = &[ #( #enum_ident::#idents, )* ];
+
+            /// Stable numeric error code
+            pub const fn to_code(self) -> u32 {
+                match self {
+                    #( #enum_ident::#idents => #codes, )*
+                }
+            }
+
+            /// Decodes error kind from its numeric code
+            pub fn from_code(code: u32) -> Option<#enum_ident> {
+                match code {
+                    #( #codes => Some(#enum_ident::#idents), )*
+                    _ => None,
+                }
+            }
+        }
+
+        impl ::std::convert::TryFrom<u32> for #enum_ident {
+            type Error = #apptools::err::UnknownErrCode;
+
+            fn try_from(code: u32) -> Result<Self, Self::Error> {
+                #enum_ident::from_code(code).ok_or(#apptools::err::UnknownErrCode(code))
+            }
+        }
+
+        impl #apptools::err::ErrKind for #enum_ident {
+
+            fn desc(&self) -> &'static str {
+                match self {
+                    #( #enum_ident::#idents => #descs, )*
+                }
+            }
+
+            fn from_io_kind(kind: ::std::io::ErrorKind) -> Self {
+                match kind {
+                    #( ::std::io::ErrorKind::#io_kinds => #enum_ident::#io_kinds, )*
+                    _ => #enum_ident::#io_fallback,
+                }
+            }
+
+            fn from_other_error() -> Self {
+                #enum_ident::#from_other
+            }
+
+            fn panicked() -> Self {
+                #enum_ident::#panicked
+            }
+
+            fn name(&self) -> &'static str {
+                match self {
+                    #( #enum_ident::#idents => #names, )*
+                }
+            }
+
+            fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    #( #names => Some(#enum_ident::#idents), )*
+                    _ => None,
+                }
+            }
+
+            fn to_code(&self) -> u32 {
+                #enum_ident::to_code(*self)
+            }
+
+            fn from_code(code: u32) -> Option<Self> {
+                #enum_ident::from_code(code)
+            }
+
+            fn category(&self) -> #apptools::err::ErrCategory {
+                match self {
+                    #( #enum_ident::#idents => #categories, )*
+                }
+            }
+
+            fn severity(&self) -> #apptools::err::ErrSeverity {
+                match self {
+                    #( #enum_ident::#idents => #severities, )*
+                }
+            }
+
+            fn is_retryable(&self) -> bool {
+                match self {
+                    #( #enum_ident::#idents => #retryables, )*
+                }
+            }
+        }
+
+        #apptools::__impl_err_kind_serde!(#enum_ident);
+    }
+}
@@ -0,0 +1,77 @@
+
+// Test assertion macros for apptools errors
+
+use super::{AppErr, ErrKind};
+
+
+/// Renders everything that helps to understand an unexpected error: the chain, brief and trail
+#[doc(hidden)]
+pub fn __assert_failure_report<K: ErrKind>(e: &AppErr<K>) -> String {
+    format!("  chain: {:#}\n  brief: {}\n  trail:\n{}", e, e.brief(), e.at.multi_line("    "))
+}
+
+
+/// Asserts that an `AppResult` is an error of the given kind and returns the error:
+///
+/// ```ignore
+/// let e = assert_app_err!(parse_port("80a"), ErrList::InvalidData);
+/// ```
+#[macro_export]
+macro_rules! assert_app_err {
+    ( $result:expr, $kind:expr $(,)? ) => {
+        match $result {
+            Ok(_) => panic!("assertion failed: expected error {:?}, got Ok", $kind),
+            Err(e) => {
+                let kind = $kind;
+                if $crate::err::ErrKind::to_code(&e.kind) != $crate::err::ErrKind::to_code(&kind) {
+                    panic!("assertion failed: expected error {:?}, got {:?}\n{}",
+                           kind, e.kind, $crate::err::__assert_failure_report(&e));
+                }
+                e
+            },
+        }
+    };
+}
+
+
+/// Asserts that an `AppResult` is an error whose message contains `pattern` and returns the error
+#[macro_export]
+macro_rules! assert_app_err_msg_contains {
+    ( $result:expr, $pattern:expr $(,)? ) => {
+        match $result {
+            Ok(_) => panic!("assertion failed: expected error with message containing {:?}, got Ok", $pattern),
+            Err(e) => {
+                let pattern: &str = $pattern;
+                if !e.msg.as_deref().map_or(false, |msg| msg.contains(pattern)) {
+                    panic!("assertion failed: expected error message containing {:?}, got {:?}\n{}",
+                           pattern, e.msg, $crate::err::__assert_failure_report(&e));
+                }
+                e
+            },
+        }
+    };
+}
+
+
+/// Asserts that an `AppResult` is an error created at `file` (path suffix) and `line`
+/// and returns the error:
+///
+/// ```ignore
+/// let e = assert_err_origin!(parse_port("80a"), "main.rs", 121);
+/// ```
+#[macro_export]
+macro_rules! assert_err_origin {
+    ( $result:expr, $file:expr, $line:expr $(,)? ) => {
+        match $result {
+            Ok(_) => panic!("assertion failed: expected error created at {}:{}, got Ok", $file, $line),
+            Err(e) => {
+                let (file, line): (&str, u32) = ($file, $line);
+                if !e.origin().map_or(false, |at| at.file().ends_with(file) && at.line() == line) {
+                    panic!("assertion failed: expected error created at {}:{}\n{}",
+                           file, line, $crate::err::__assert_failure_report(&e));
+                }
+                e
+            },
+        }
+    };
+}
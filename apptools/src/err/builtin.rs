@@ -0,0 +1,121 @@
+
+// Built-in error kinds shared by `declare_app_errors!` and `#[derive(AppErrorKind)]`
+
+use super::{ErrCategory, ErrSeverity};
+
+
+/// Description and metadata of a built-in error kind
+#[derive(Debug, Copy, Clone)]
+pub struct BuiltinKind {
+    pub name: &'static str,
+    pub desc: &'static str,
+    pub category: ErrCategory,
+    pub severity: ErrSeverity,
+    pub retryable: bool,
+}
+
+
+const fn builtin(name: &'static str, desc: &'static str, category: ErrCategory,
+                 severity: ErrSeverity, retryable: bool) -> BuiltinKind {
+    BuiltinKind { name, desc, category, severity, retryable }
+}
+
+
+use ErrCategory::*;
+use ErrSeverity::*;
+
+macro_rules! builtin_kinds {
+    ( $( $group:ident: [ $( $Name:ident = $Code:expr, $Desc:expr
+                            => { category: $Cat:ident, severity: $Sev:ident, retryable: $Retry:literal }, )* ], )* ) => {
+
+        /// Built-in error kinds, the index is the stable error code;
+        /// `std::io::ErrorKind` counterparts go first, followed by `FromOtherError` and `Panicked`.
+        pub const BUILTIN_KINDS: &[BuiltinKind] = &[
+            $( $( builtin(stringify!($Name), $Desc, $Cat, $Sev, $Retry), )* )*
+        ];
+
+        // Codes of the built-in kinds must be their indexes in `BUILTIN_KINDS`
+        const _: () = {
+            let mut code = 0;
+            $( $(
+                assert!($Code == code, "built-in error kind codes must be sequential");
+                code += 1;
+            )* )*
+            assert!(code < super::USER_ERR_CODE_BASE, "built-in error kind codes overlap user-defined ones");
+        };
+    };
+}
+
+crate::__builtin_err_kinds!([builtin_kinds]);
+
+
+#[cfg(all(test, feature = "derive"))]
+mod tests {
+
+    use super::BUILTIN_KINDS;
+    use crate::err::{ErrKind, ErrCategory, ErrSeverity};
+    use crate::AppErrorKind;
+
+    crate::declare_app_errors!(MacroErr, MacroErrTable,
+        Custom, "custom error"
+    );
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, AppErrorKind)]
+    pub enum DerivedErr {
+        NotFound, PermissionDenied, ConnectionRefused, ConnectionReset, ConnectionAborted,
+        NotConnected, AddrInUse, AddrNotAvailable, BrokenPipe, AlreadyExists, WouldBlock,
+        InvalidInput, InvalidData, TimedOut, WriteZero, Interrupted, Other, UnexpectedEof,
+        FromOtherError, Panicked,
+
+        /// custom error
+        Custom,
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, AppErrorKind)]
+    #[app_err(std_kinds = false)]
+    pub enum NoStdErr {
+        #[desc = "not found in cache"]
+        NotFound,
+
+        #[desc = "foreign error"]
+        #[app_err(from_other, category = Internal, severity = Critical)]
+        Foreign,
+
+        #[cfg(test)]
+        #[desc = "test only"]
+        #[app_err(code = 1100)]
+        TestOnly,
+
+        #[desc = "after a cfg-gated kind"]
+        #[app_err(code = 1200)]
+        AfterTestOnly,
+    }
+
+    #[test]
+    fn derive_matches_declare_app_errors() {
+        assert_eq!(MacroErr::ALL.len(), DerivedErr::ALL.len());
+        for (m, d) in MacroErr::ALL.iter().zip(DerivedErr::ALL) {
+            assert_eq!(m.name(), d.name());
+            assert_eq!(m.to_code(), d.to_code());
+            assert_eq!(m.desc(), d.desc());
+            assert_eq!(m.category(), d.category());
+            assert_eq!(m.severity(), d.severity());
+            assert_eq!(m.is_retryable(), d.is_retryable());
+        }
+        for (i, b) in BUILTIN_KINDS.iter().enumerate() {
+            assert_eq!(DerivedErr::from_code(i as u32).unwrap().name(), b.name);
+        }
+    }
+
+    #[test]
+    fn derive_without_std_kinds() {
+        assert_eq!(NoStdErr::NotFound.to_code(), 1000);
+        assert_eq!(NoStdErr::NotFound.desc(), "not found in cache");
+        assert_eq!(NoStdErr::from_io_kind(std::io::ErrorKind::NotFound), NoStdErr::Foreign);
+        assert_eq!(NoStdErr::from_other_error(), NoStdErr::Foreign);
+        assert_eq!(NoStdErr::Foreign.category(), ErrCategory::Internal);
+        assert_eq!(NoStdErr::Foreign.severity(), ErrSeverity::Critical);
+        assert_eq!(NoStdErr::TestOnly.to_code(), 1100);
+        assert_eq!(NoStdErr::from_code(1200), Some(NoStdErr::AfterTestOnly));
+    }
+}
@@ -0,0 +1,69 @@
+// Built-in error kinds: the single source of `BUILTIN_KINDS`, of the kinds declared by `declare_app_errors!`
+// and of the kinds recognized by `#[derive(AppErrorKind)]`; apptools-derive can't depend on apptools,
+// so it `include!`s this file, which must not refer to anything else.
+
+
+/// Code of the first user-defined error kind;
+/// codes below are reserved for the built-in kinds declared by `declare_app_errors!`.
+pub const USER_ERR_CODE_BASE: u32 = 1000;
+
+
+/// Invokes `callback!` with `args` followed by the built-in error kinds:
+/// `io: [..], other: [..],`, each kind is `Name = code, "description" => { category: .., severity: .., retryable: .. },`
+#[doc(hidden)]
+#[cfg_attr(not(proc_macro), macro_export)]
+macro_rules! __builtin_err_kinds {
+    ( [ $($callback:tt)* ] $($args:tt)* ) => {
+        $($callback)*! { $($args)*
+
+            // std::io::ErrorKind counterparts;
+            // !Codes are part of the wire format, never change them!
+            io: [
+                NotFound = 0, "an entity (possibly a file) could not be found"
+                    => { category: Io, severity: Error, retryable: false },
+                PermissionDenied = 1, "operation lacked necessary privileges to complete"
+                    => { category: Security, severity: Error, retryable: false },
+                ConnectionRefused = 2, "connection was refused by the remote server"
+                    => { category: Network, severity: Error, retryable: true },
+                ConnectionReset = 3, "connection was reset by the remote server"
+                    => { category: Network, severity: Warning, retryable: true },
+                ConnectionAborted = 4, "connection was aborted (terminated) by the remote server"
+                    => { category: Network, severity: Warning, retryable: true },
+                NotConnected = 5, "network operation failed because it was not connected yet"
+                    => { category: Network, severity: Error, retryable: false },
+                AddrInUse = 6, "socket address could not be bound because the address is already in use elsewhere"
+                    => { category: Network, severity: Error, retryable: false },
+                AddrNotAvailable = 7, "nonexistent interface was requested or the requested address was not local"
+                    => { category: Network, severity: Error, retryable: false },
+                BrokenPipe = 8, "operation failed because a pipe was closed"
+                    => { category: Io, severity: Warning, retryable: false },
+                AlreadyExists = 9, "entity (possibly a file) already exists"
+                    => { category: Io, severity: Error, retryable: false },
+                WouldBlock = 10, "operation needs to block to complete, but the blocking operation was requested to not occur"
+                    => { category: Io, severity: Debug, retryable: true },
+                InvalidInput = 11, "parameter was incorrect"
+                    => { category: Data, severity: Error, retryable: false },
+                InvalidData = 12, "data not valid for the operation were encountered"
+                    => { category: Data, severity: Error, retryable: false },
+                TimedOut = 13, "I/O operation's timeout expired, causing it to be canceled"
+                    => { category: Io, severity: Warning, retryable: true },
+                WriteZero = 14, "operation could not be completed because a call to write returned Ok(0)"
+                    => { category: Io, severity: Error, retryable: false },
+                Interrupted = 15, "operation was interrupted"
+                    => { category: Io, severity: Debug, retryable: true },
+                Other = 16, ""
+                    => { category: General, severity: Error, retryable: false },
+                UnexpectedEof = 17, "operation could not be completed because 'end of file' was reached prematurely"
+                    => { category: Io, severity: Error, retryable: false },
+            ],
+
+            // Other error kinds
+            other: [
+                FromOtherError = 18, ""  // occurred due to another error
+                    => { category: General, severity: Error, retryable: false },
+                Panicked = 19, "a thread or task panicked"
+                    => { category: Internal, severity: Critical, retryable: false },
+            ],
+        }
+    };
+}
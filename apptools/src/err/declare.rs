@@ -0,0 +1,236 @@
+
+/// Macro that creates app-specific error enum, the corresponding error description lookup table
+/// and implements `apptools::err::ErrKind` for the enum;
+///
+/// Note: `#[derive(AppErrorKind)]` (the `derive` feature) generates the same items for a normal enum
+/// and also supports doc comments, attributes and cfg gates on error kinds.
+///
+/// `std::io::ErrorKind` counterparts, `FromOtherError` and `Panicked` are always included at the beginning
+/// and have fixed codes below `USER_ERR_CODE_BASE`. User-defined kinds are numbered sequentially
+/// starting from `USER_ERR_CODE_BASE`, a kind can pin its own code:
+///
+/// ```ignore
+/// declare_app_errors!(ErrList, ErrListLookupTable,
+///     UserHasNoProfile, "user has no profile",           // 1000
+///     RequestLimitExceeded = 1100, "too many requests",  // 1100
+///     DummyError, "a dummy error"                        // 1101
+/// );
+/// ```
+///
+/// Each kind can optionally declare its category, severity and retryability,
+/// omitted values default to `General`, `Error` and `false`, other keys don't compile:
+///
+/// ```ignore
+/// declare_app_errors!(ErrList, ErrListLookupTable,
+///     AuthServerBusy, "auth server is busy" => { category: Network, severity: Warning, retryable: true },
+///     TokenRevoked, "token was revoked" => { category: Security }
+/// );
+/// ```
+#[macro_export]
+macro_rules! declare_app_errors {
+    ( $EnumIdent:ident, $EnumDescIdent:ident,
+      $FirstElem:ident $(= $FirstCode:expr)?, $FirstDescStr:expr $(=> { $($FirstMeta:tt)* })?
+      $(, $EnumElem:ident $(= $EnumCode:expr)?, $EnumDescStr:expr $(=> { $($EnumMeta:tt)* })? )* $(,)? ) => {
+
+        $crate::__builtin_err_kinds!([$crate::declare_app_errors] @builtin $EnumIdent, $EnumDescIdent,
+
+            // User-defined error kinds
+            user: [
+                $FirstElem = $crate::__user_err_code!($($FirstCode)?), $FirstDescStr
+                    => { $($($FirstMeta)*)? }
+                $(, $EnumElem $(= $EnumCode)?, $EnumDescStr => { $($($EnumMeta)*)? } )*
+            ],
+        );
+    };
+
+    // Built-in error kinds (see `__builtin_err_kinds!`) go first, their codes are fixed
+    ( @builtin $EnumIdent:ident, $EnumDescIdent:ident, user: [ $($User:tt)* ],
+      io: [ $( $Io:ident = $IoCode:expr, $IoDesc:expr => { $($IoMeta:tt)* }, )* ],
+      other: [ $( $Other:ident = $OtherCode:expr, $OtherDesc:expr => { $($OtherMeta:tt)* }, )* ], ) => {
+
+        $crate::declare_app_errors!(@declare $EnumIdent, $EnumDescIdent, [ $($Io),* ],
+            $( $Io = $IoCode, $IoDesc => { $($IoMeta)* }, )*
+            $( $Other = $OtherCode, $OtherDesc => { $($OtherMeta)* }, )*
+            $($User)*
+        );
+    };
+
+    ( @declare $EnumIdent:ident, $EnumDescIdent:ident, [ $($IoKind:ident),* ],
+      $( $Elem:ident $(= $Code:expr)?, $Desc:expr => { $($Meta:tt)* } ),+ ) => {
+
+        #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+        #[allow(unused)]
+        #[repr(u32)]
+        pub enum $EnumIdent {  // error enum
+            $(
+                $Elem $(= $Code)?,
+            )+
+        }
+
+        #[allow(unused)]
+        #[allow(non_upper_case_globals)]
+        pub const $EnumDescIdent: &[&str] = &[  // error description table (exactly matches the enum order)
+            $(
+                $Desc,
+            )+
+        ];
+
+
+        #[allow(unused)]
+        impl $EnumIdent {
+
+            /// All error kinds in declaration order
+            pub const ALL: &'static [$EnumIdent] = &[ $( $EnumIdent::$Elem, )+ ];
+
+            /// Stable numeric error code
+            pub const fn to_code(self) -> u32 {
+                self as u32
+            }
+
+            /// Decodes error kind from its numeric code
+            pub fn from_code(code: u32) -> Option<$EnumIdent> {
+                $(
+                    if code == $EnumIdent::$Elem as u32 {
+                        return Some($EnumIdent::$Elem);
+                    }
+                )+
+                None
+            }
+        }
+
+        // The description table must describe every error kind
+        const _: () = assert!($EnumDescIdent.len() == $EnumIdent::ALL.len(),
+                              "error description table and error enum lengths differ");
+
+
+        impl std::convert::TryFrom<u32> for $EnumIdent {
+            type Error = $crate::err::UnknownErrCode;
+
+            fn try_from(code: u32) -> Result<Self, Self::Error> {
+                $EnumIdent::from_code(code).ok_or($crate::err::UnknownErrCode(code))
+            }
+        }
+
+
+        impl $crate::err::ErrKind for $EnumIdent {
+
+            fn desc(&self) -> &'static str {
+                match self {
+                    $(
+                        $EnumIdent::$Elem => $Desc,
+                    )+
+                }
+            }
+
+            fn from_io_kind(kind: std::io::ErrorKind) -> Self {
+                use std::io::ErrorKind;
+
+                match kind {
+                    $(
+                        ErrorKind::$IoKind => $EnumIdent::$IoKind,
+                    )*
+                    _ => $EnumIdent::Other,
+                }
+            }
+
+            fn from_other_error() -> Self {
+                $EnumIdent::FromOtherError
+            }
+
+            fn panicked() -> Self {
+                $EnumIdent::Panicked
+            }
+
+            fn name(&self) -> &'static str {
+                match self {
+                    $(
+                        $EnumIdent::$Elem => stringify!($Elem),
+                    )+
+                }
+            }
+
+            fn from_name(name: &str) -> Option<Self> {
+                $(
+                    if name == stringify!($Elem) {
+                        return Some($EnumIdent::$Elem);
+                    }
+                )+
+                None
+            }
+
+            fn to_code(&self) -> u32 {
+                $EnumIdent::to_code(*self)
+            }
+
+            fn from_code(code: u32) -> Option<Self> {
+                $EnumIdent::from_code(code)
+            }
+
+            fn category(&self) -> $crate::err::ErrCategory {
+                match self {
+                    $(
+                        $EnumIdent::$Elem => $crate::__err_kind_meta!(category; $($Meta)*),
+                    )+
+                }
+            }
+
+            fn severity(&self) -> $crate::err::ErrSeverity {
+                match self {
+                    $(
+                        $EnumIdent::$Elem => $crate::__err_kind_meta!(severity; $($Meta)*),
+                    )+
+                }
+            }
+
+            fn is_retryable(&self) -> bool {
+                match self {
+                    $(
+                        $EnumIdent::$Elem => $crate::__err_kind_meta!(retryable; $($Meta)*),
+                    )+
+                }
+            }
+        }
+
+        $crate::__impl_err_kind_serde!($EnumIdent);
+    };
+}
+
+
+/// Code of the first user-defined error kind, unless it is pinned explicitly
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __user_err_code {
+    () => { $crate::err::USER_ERR_CODE_BASE };
+    ( $code:expr ) => { $code };
+}
+
+
+/// Extracts a metadata value from `{ category: .., severity: .., retryable: .. }`
+/// of an error kind, falls back to the default if the value is omitted
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __err_kind_meta {
+    ( category; ) => { $crate::err::ErrCategory::General };
+    ( severity; ) => { $crate::err::ErrSeverity::Error };
+    ( retryable; ) => { false };
+
+    ( category; category: $val:ident $(, $($rest:tt)*)? ) => { $crate::err::ErrCategory::$val };
+    ( severity; severity: $val:ident $(, $($rest:tt)*)? ) => { $crate::err::ErrSeverity::$val };
+    ( retryable; retryable: $val:literal $(, $($rest:tt)*)? ) => { $val };
+
+    // Skip the other known keys
+    ( $key:ident; category: $val:ident $(, $($rest:tt)*)? ) => {
+        $crate::__err_kind_meta!($key; $($($rest)*)?)
+    };
+    ( $key:ident; severity: $val:ident $(, $($rest:tt)*)? ) => {
+        $crate::__err_kind_meta!($key; $($($rest)*)?)
+    };
+    ( $key:ident; retryable: $val:literal $(, $($rest:tt)*)? ) => {
+        $crate::__err_kind_meta!($key; $($($rest)*)?)
+    };
+
+    ( $key:ident; $($invalid:tt)* ) => {
+        compile_error!(concat!("invalid error kind metadata `", stringify!($($invalid)*),
+                               "`, expected `category: ..`, `severity: ..` or `retryable: ..`"))
+    };
+}
@@ -0,0 +1,181 @@
+
+// Context extension methods for `Result` and `Option`
+
+use std::error::Error;
+use std::io;
+use std::panic::Location;
+
+use super::{foreign_io_kind, AppErr, AppResult, BoxedErr, ErrKind};
+
+
+/// Converts a failed `Result` or an empty `Option` into `AppErr`, recording the caller location;
+///
+/// Error kind is inferred from the function return type when the result is propagated with `?`:
+///
+/// ```ignore
+/// fn read_port(path: &str) -> AppResult<u16> {
+///     let s = std::fs::read_to_string(path).with_ctx(|| format!("reading '{}'", path))?;
+///     let port = s.trim().parse().kind(ErrList::InvalidInput)?;
+///     let user = find_user(port).or_app_err(ErrList::UserHasNoProfile)?;
+///     ...
+/// }
+/// ```
+///
+/// A wrapped error becomes the source of the new `AppErr`. Kind of the new error is taken from
+/// the wrapped one: `AppErr` keeps its kind, `std::io::Error` is mapped with `ErrKind::from_io_kind()`,
+/// other errors get the same kind as when converted with `?` (e.g. `ParseIntError` becomes `InvalidData`),
+/// or `FromOtherError` if they have no `From` impl. An empty `Option` becomes `NotFound`.
+pub trait AppResultExt<T> {
+
+    /// Adds a message to the error
+    fn ctx<K: ErrKind, M: Into<String>>(self, msg: M) -> AppResult<T, K>;
+
+    /// Adds a lazily evaluated message to the error
+    fn with_ctx<K: ErrKind, M: Into<String>, F: FnOnce() -> M>(self, f: F) -> AppResult<T, K>;
+
+    /// Sets the error kind
+    fn kind<K: ErrKind>(self, kind: K) -> AppResult<T, K>;
+
+    /// Same as `kind()`, reads better on `Option`
+    fn or_app_err<K: ErrKind>(self, kind: K) -> AppResult<T, K>;
+}
+
+
+/// Kind of an `AppErr` that wraps `source`
+fn kind_of_source<K: ErrKind>(source: &BoxedErr) -> K {
+    if let Some(e) = source.downcast_ref::<AppErr<K>>() {
+        e.kind
+    } else if let Some(e) = source.downcast_ref::<io::Error>() {
+        K::from_io_kind(e.kind())
+    } else if let Some(io_kind) = foreign_io_kind(source) {
+        K::from_io_kind(io_kind)
+    } else {
+        K::from_other_error()
+    }
+}
+
+
+fn wrap<K: ErrKind>(kind: Option<K>, at: &'static Location<'static>, msg: Option<String>,
+                    source: BoxedErr) -> AppErr<K> {
+    let kind = kind.unwrap_or_else(|| kind_of_source(&source));
+    if source.is::<AppErr<K>>() {
+        AppErr::from_counted(kind, at, msg, source)
+    } else {
+        AppErr::from_other(kind, at, msg, source)
+    }
+}
+
+
+impl<T, E: Error + Send + Sync + 'static> AppResultExt<T> for Result<T, E> {
+
+    #[track_caller]
+    fn ctx<K: ErrKind, M: Into<String>>(self, msg: M) -> AppResult<T, K> {
+        match self {
+            Ok(val) => Ok(val),
+            Err(e) => Err(wrap(None, Location::caller(), Some(msg.into()), Box::new(e))),
+        }
+    }
+
+    #[track_caller]
+    fn with_ctx<K: ErrKind, M: Into<String>, F: FnOnce() -> M>(self, f: F) -> AppResult<T, K> {
+        match self {
+            Ok(val) => Ok(val),
+            Err(e) => Err(wrap(None, Location::caller(), Some(f().into()), Box::new(e))),
+        }
+    }
+
+    #[track_caller]
+    fn kind<K: ErrKind>(self, kind: K) -> AppResult<T, K> {
+        match self {
+            Ok(val) => Ok(val),
+            Err(e) => Err(wrap(Some(kind), Location::caller(), None, Box::new(e))),
+        }
+    }
+
+    #[track_caller]
+    fn or_app_err<K: ErrKind>(self, kind: K) -> AppResult<T, K> {
+        match self {
+            Ok(val) => Ok(val),
+            Err(e) => Err(wrap(Some(kind), Location::caller(), None, Box::new(e))),
+        }
+    }
+}
+
+
+impl<T> AppResultExt<T> for Option<T> {
+
+    #[track_caller]
+    fn ctx<K: ErrKind, M: Into<String>>(self, msg: M) -> AppResult<T, K> {
+        match self {
+            Some(val) => Ok(val),
+            None => Err(AppErr::new(K::from_io_kind(io::ErrorKind::NotFound), Location::caller(), Some(msg.into()))),
+        }
+    }
+
+    #[track_caller]
+    fn with_ctx<K: ErrKind, M: Into<String>, F: FnOnce() -> M>(self, f: F) -> AppResult<T, K> {
+        match self {
+            Some(val) => Ok(val),
+            None => Err(AppErr::new(K::from_io_kind(io::ErrorKind::NotFound), Location::caller(), Some(f().into()))),
+        }
+    }
+
+    #[track_caller]
+    fn kind<K: ErrKind>(self, kind: K) -> AppResult<T, K> {
+        match self {
+            Some(val) => Ok(val),
+            None => Err(AppErr::new(kind, Location::caller(), None)),
+        }
+    }
+
+    #[track_caller]
+    fn or_app_err<K: ErrKind>(self, kind: K) -> AppResult<T, K> {
+        match self {
+            Some(val) => Ok(val),
+            None => Err(AppErr::new(kind, Location::caller(), None)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::AppResultExt;
+    use crate::err::AppResult;
+
+    crate::declare_app_errors!(TestErr, TestErrTable,
+        TokenExpired, "token expired"
+    );
+
+    fn parse(s: &str) -> AppResult<u32, TestErr> {
+        let val = s.parse::<u32>().ctx("parsing token id")?;
+        Ok(val)
+    }
+
+    #[test]
+    fn ctx_wraps_error_at_caller() {
+        let e = parse("x").unwrap_err();
+        assert_eq!(e.kind, TestErr::InvalidData);
+        let converted: crate::err::AppErr<TestErr> = "x".parse::<u32>().unwrap_err().into();
+        assert_eq!(e.kind, converted.kind);  // same kind as with `?`
+        assert_eq!(e.msg.as_deref(), Some("parsing token id"));
+        assert_eq!(e.origin().unwrap().file(), file!());
+        assert_eq!(e.source.unwrap().to_string(), "invalid digit found in string");
+
+        // AppErr keeps its kind when wrapped
+        let e: crate::err::AppErr<TestErr> = parse("x").kind(TestErr::TokenExpired)
+            .with_ctx(|| format!("token {}", 1)).unwrap_err();
+        assert_eq!(e.kind, TestErr::TokenExpired);
+        assert_eq!(e.chain().count(), 4);  // ctx -> kind -> parse -> ParseIntError
+    }
+
+    #[test]
+    fn option_into_app_err() {
+        let r: AppResult<u32, TestErr> = None.or_app_err(TestErr::TokenExpired);
+        assert_eq!(r.unwrap_err().kind, TestErr::TokenExpired);
+        let r: AppResult<u32, TestErr> = None.ctx("no token");
+        assert_eq!(r.unwrap_err().kind, TestErr::NotFound);
+        assert_eq!(Some(5).kind(TestErr::TokenExpired).unwrap(), 5);
+    }
+}
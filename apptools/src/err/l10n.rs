@@ -0,0 +1,92 @@
+
+// Localized error descriptions via Fluent (enabled by the `fluent` feature)
+// https://github.com/XAMPPRocky/fluent-templates
+
+use std::borrow::Cow;
+
+pub use fluent_templates::Loader;
+pub use unic_langid::LanguageIdentifier;
+
+use super::ErrKind;
+
+
+/// Prefix of Fluent message IDs of error kinds
+pub const ERR_MSG_ID_PREFIX: &str = "err-";
+
+/// Placeholder returned by `fluent_templates` loaders when a message is missing
+const UNKNOWN_LOCALIZATION: &str = "Unknown localization ";
+
+
+/// Fluent message ID of the error kind, derived from the enum variant name:
+/// `NotFound` -> `err-not-found`, `RequestLimitExceeded` -> `err-request-limit-exceeded`.
+pub fn err_msg_id<K: ErrKind>(kind: &K) -> String {
+    let name = kind.name();
+    let mut id = String::with_capacity(ERR_MSG_ID_PREFIX.len() + name.len() + 4);
+    id += ERR_MSG_ID_PREFIX;
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                id.push('-');
+            }
+            id.extend(c.to_lowercase());
+        } else {
+            id.push(c);
+        }
+    }
+    id
+}
+
+
+/// Returns description of the error kind in the requested language;
+///
+/// Lookup follows the loader's fallback chain (e.g. `uk` -> `en-US`),
+/// if the message is missing in all bundles, the built-in English description is returned.
+pub fn app_err_desc_lang<K: ErrKind, L: Loader>(kind: &K, loader: &L, lang: &LanguageIdentifier)
+                                                -> Cow<'static, str> {
+    let id = err_msg_id(kind);
+    let desc = loader.lookup(lang, &id);
+    if desc.starts_with(UNKNOWN_LOCALIZATION) {
+        Cow::Borrowed(kind.desc())
+    } else {
+        Cow::Owned(desc)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    crate::declare_app_errors!(TestErr, TestErrTable,
+        RequestLimitExceeded, "user exceeded the number of requests per time unit"
+    );
+
+    #[test]
+    fn msg_ids_are_kebab_case() {
+        assert_eq!(err_msg_id(&TestErr::NotFound), "err-not-found");
+        assert_eq!(err_msg_id(&TestErr::UnexpectedEof), "err-unexpected-eof");
+        assert_eq!(err_msg_id(&TestErr::RequestLimitExceeded), "err-request-limit-exceeded");
+    }
+
+    fluent_templates::static_loader! {
+        static LOC = {
+            locales: "../LOCALIZATION/p1_fluent_templates/locales",
+            fallback_language: "en-US",
+            customise: |bundle| bundle.set_use_isolating(false),
+        };
+    }
+
+    #[test]
+    fn desc_lookup_falls_back_to_builtin_desc() {
+        let uk: LanguageIdentifier = "uk".parse().unwrap();
+
+        // Translated
+        assert_eq!(app_err_desc_lang(&TestErr::NotFound, &*LOC, &uk), "об'єкт (можливо, файл) не знайдено");
+        // Missing in uk, found in the en-US fallback bundle
+        assert_eq!(app_err_desc_lang(&TestErr::TimedOut, &*LOC, &uk), "operation timed out");
+        // Missing in all bundles
+        let desc = app_err_desc_lang(&TestErr::RequestLimitExceeded, &*LOC, &uk);
+        assert_eq!(desc, TestErr::RequestLimitExceeded.desc());
+    }
+}
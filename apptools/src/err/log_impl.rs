@@ -0,0 +1,143 @@
+
+// `log` crate integration (enabled by the `log` feature)
+
+use super::ErrSeverity;
+
+
+/// Log level an error of the given severity is logged at by `log_app_err!` and `succ_log!`
+pub fn severity_log_level(severity: ErrSeverity) -> log::Level {
+    match severity {
+        ErrSeverity::Debug => log::Level::Debug,
+        ErrSeverity::Info => log::Level::Info,
+        ErrSeverity::Warning => log::Level::Warn,
+        ErrSeverity::Error | ErrSeverity::Critical => log::Level::Error,
+    }
+}
+
+
+/// Logs an `AppErr` as a structured record with `kind`, `code`, `os_code` and `trail` key-values;
+///
+/// The level is picked from the error severity unless given explicitly, the record target
+/// is the caller module:
+///
+/// ```ignore
+/// log_app_err!(e);
+/// log_app_err!(log::Level::Trace, e);
+/// ```
+#[macro_export]
+macro_rules! log_app_err {
+    ( $level:expr, $e:expr $(,)? ) => {{
+        let e = &$e;
+        $crate::log::log!($level,
+            kind = $crate::err::ErrKind::name(&e.kind),
+            code = $crate::err::ErrKind::to_code(&e.kind),
+            os_code:? = e.code,
+            trail:% = e.at;
+            "{:#}", e)
+    }};
+    ( $e:expr $(,)? ) => {{
+        let e = &$e;
+        $crate::log_app_err!($crate::err::severity_log_level(e.severity()), e)
+    }};
+}
+
+
+/// Same as `succ!`, but also logs the error with `log_app_err!` after appending the current location
+#[macro_export]
+macro_rules! succ_log {
+    ( $level:expr, $x:expr $(,)? ) => {
+        $x.map_err(|mut e| {
+            e.append_code_loc(std::panic::Location::caller());
+            $crate::log_app_err!($level, e);
+            e
+        } )?
+    };
+    ( $x:expr $(,)? ) => {
+        $x.map_err(|mut e| {
+            e.append_code_loc(std::panic::Location::caller());
+            $crate::log_app_err!(e);
+            e
+        } )?
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    use log::kv::{Key, Value, VisitSource};
+    use crate::err::AppResult;
+
+    crate::declare_app_errors!(TestErr, TestErrTable,
+        TokenExpired, "token expired" => { severity: Warning }
+    );
+
+    struct Record {
+        level: log::Level,
+        msg: String,
+        kv: BTreeMap<String, String>,
+    }
+
+    struct TestLogger(Mutex<Vec<Record>>);
+
+    struct Collect<'a>(&'a mut BTreeMap<String, String>);
+
+    impl<'kvs> VisitSource<'kvs> for Collect<'_> {
+        fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+            self.0.insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+    }
+
+    impl log::Log for TestLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool { true }
+
+        fn log(&self, record: &log::Record) {
+            let mut kv = BTreeMap::new();
+            record.key_values().visit(&mut Collect(&mut kv)).unwrap();
+            self.0.lock().unwrap().push(Record { level: record.level(), msg: record.args().to_string(), kv });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: TestLogger = TestLogger(Mutex::new(Vec::new()));
+
+    fn check_token(expired: bool) -> AppResult<u32, TestErr> {
+        if expired {
+            return Err(crate::app_err!(TestErr::TokenExpired, None));
+        }
+        Ok(1)
+    }
+
+    fn handle_request(level: Option<log::Level>) -> AppResult<u32, TestErr> {
+        let val = match level {
+            Some(level) => crate::succ_log!(level, check_token(true)),
+            None => crate::succ_log!(check_token(true)),
+        };
+        Ok(val)
+    }
+
+    #[test]
+    fn logs_structured_record() {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let e = handle_request(None).unwrap_err();
+        assert_eq!(e.at.len(), 2);
+        let _ = handle_request(Some(log::Level::Trace));
+
+        let records = LOGGER.0.lock().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].level, log::Level::Warn);
+        assert_eq!(records[1].level, log::Level::Trace);
+        assert_eq!(records[0].msg, format!("{:#}", e));
+        assert_eq!(records[0].kv["kind"], "TokenExpired");
+        assert_eq!(records[0].kv["code"], "1000");
+        assert_eq!(records[0].kv["os_code"], "None");
+        assert_eq!(records[0].kv["trail"], e.at.to_string());
+    }
+}
@@ -0,0 +1,354 @@
+
+// Error metrics: number of created AppErr values by kind and by origin location and kind
+
+use std::fmt::Write;
+use std::panic::Location;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, Ordering};
+
+use super::ErrKind;
+
+
+/// Maximum number of distinct error kinds counted by `ErrMetrics`
+pub const ERR_METRICS_MAX_KINDS: usize = 256;
+
+/// Maximum number of distinct (origin location, kind) pairs counted by `ErrMetrics`
+pub const ERR_METRICS_MAX_SITES: usize = 1024;
+
+// Maximum number of slots probed before a site is counted as untracked
+const MAX_SITE_PROBES: usize = 16;
+
+
+struct KindSlot {
+    key: AtomicU32,  // error kind code + 1, 0 means empty slot
+    count: AtomicU64,
+}
+
+
+struct SiteSlot {
+    at: AtomicPtr<Location<'static>>,  // null means empty slot
+    key: AtomicU32,  // error kind code + 1, 0 means the slot is being claimed
+    count: AtomicU64,
+}
+
+
+/// Lock-free registry of error counters;
+///
+/// Counters are fixed-size open addressing tables, so recording an error never allocates or locks.
+/// Kinds are stored by code, a snapshot maps codes back to the app's error kind enum.
+/// The registry is usually installed globally with `install_err_metrics()` or `set_err_metrics()`,
+/// after that every `AppErr::new()`, `from_std()` and `from_other()` is reported to it,
+/// wrapping an already counted error (`from_counted()`) is not.
+pub struct ErrMetrics {
+    kinds: [KindSlot; ERR_METRICS_MAX_KINDS],
+    sites: [SiteSlot; ERR_METRICS_MAX_SITES],
+
+    /// Errors that did not fit into the kind table
+    untracked_kinds: AtomicU64,
+
+    /// Errors whose origin location did not fit into the site table
+    untracked_sites: AtomicU64,
+}
+
+
+impl ErrMetrics {
+
+    pub const fn new() -> ErrMetrics {
+        ErrMetrics {
+            kinds: [const { KindSlot { key: AtomicU32::new(0), count: AtomicU64::new(0) } }; ERR_METRICS_MAX_KINDS],
+            sites: [const { SiteSlot { at: AtomicPtr::new(ptr::null_mut()), key: AtomicU32::new(0),
+                                       count: AtomicU64::new(0) } }; ERR_METRICS_MAX_SITES],
+            untracked_kinds: AtomicU64::new(0),
+            untracked_sites: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts an error of the kind `code` created at `at`
+    pub fn record(&self, code: u32, at: &'static Location<'static>) {
+        let key = code.wrapping_add(1);
+        self.record_kind(key);
+        self.record_site(key, at);
+    }
+
+    fn record_kind(&self, key: u32) {
+        let start = (key as usize).wrapping_mul(0x9E37_79B9) % ERR_METRICS_MAX_KINDS;
+        for i in 0..ERR_METRICS_MAX_KINDS {
+            let slot = &self.kinds[(start + i) % ERR_METRICS_MAX_KINDS];
+            match slot.key.compare_exchange(0, key, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {},
+                Err(cur) if cur == key => {},
+                Err(_) => continue,
+            }
+            slot.count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.untracked_kinds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_site(&self, key: u32, at: &'static Location<'static>) {
+        let at_ptr = at as *const Location<'static> as *mut Location<'static>;
+        let start = ((at_ptr as usize >> 3) ^ key as usize).wrapping_mul(0x9E37_79B9) % ERR_METRICS_MAX_SITES;
+        for i in 0..MAX_SITE_PROBES {
+            let slot = &self.sites[(start + i) % ERR_METRICS_MAX_SITES];
+            match slot.at.compare_exchange(ptr::null_mut(), at_ptr, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {},
+                Err(cur) if cur == at_ptr => {},
+                Err(_) => continue,
+            }
+            // A slot is claimed by location first, then by kind
+            match slot.key.compare_exchange(0, key, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => {},
+                Err(cur) if cur == key => {},
+                Err(_) => continue,
+            }
+            slot.count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.untracked_sites.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes a snapshot of the counters with at most `top_n` origin locations;
+    ///
+    /// Kinds that `K` can't decode (e.g. recorded by another error enum) are counted as untracked.
+    pub fn snapshot<K: ErrKind>(&self, top_n: usize) -> ErrMetricsSnapshot<K> {
+        let mut snapshot = ErrMetricsSnapshot {
+            total: 0,
+            by_kind: Vec::new(),
+            top_origins: Vec::new(),
+            untracked: self.untracked_kinds.load(Ordering::Relaxed),
+            untracked_origins: self.untracked_sites.load(Ordering::Relaxed),
+        };
+
+        for slot in &self.kinds {
+            let key = slot.key.load(Ordering::Acquire);
+            let count = slot.count.load(Ordering::Relaxed);
+            if key == 0 || count == 0 {
+                continue;
+            }
+            match K::from_code(key - 1) {
+                Some(kind) => snapshot.by_kind.push((kind, count)),
+                None => snapshot.untracked += count,
+            }
+        }
+        snapshot.by_kind.sort_by_key(|(kind, _)| kind.to_code());
+        snapshot.total = snapshot.by_kind.iter().map(|(_, count)| count).sum::<u64>() + snapshot.untracked;
+
+        for slot in &self.sites {
+            let at_ptr = slot.at.load(Ordering::Acquire);
+            let count = slot.count.load(Ordering::Relaxed);
+            if at_ptr.is_null() || count == 0 {
+                continue;
+            }
+            let kind = match slot.key.load(Ordering::Acquire).checked_sub(1).and_then(K::from_code) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            // SAFETY: only `&'static Location` values are stored in the site table
+            let at: &'static Location<'static> = unsafe { &*at_ptr };
+            snapshot.top_origins.push(ErrOrigin { at, kind, count });
+        }
+        snapshot.top_origins.sort_by(|a, b| b.count.cmp(&a.count)
+            .then_with(|| a.at.file().cmp(b.at.file()))
+            .then_with(|| a.at.line().cmp(&b.at.line())));
+        snapshot.top_origins.truncate(top_n);
+
+        snapshot
+    }
+
+    /// Resets all counters; concurrently recorded errors may be partially lost
+    pub fn reset(&self) {
+        for slot in &self.kinds {
+            slot.count.store(0, Ordering::Relaxed);
+        }
+        for slot in &self.sites {
+            slot.count.store(0, Ordering::Relaxed);
+        }
+        self.untracked_kinds.store(0, Ordering::Relaxed);
+        self.untracked_sites.store(0, Ordering::Relaxed);
+    }
+}
+
+
+impl Default for ErrMetrics {
+
+    fn default() -> Self {
+        ErrMetrics::new()
+    }
+}
+
+
+/// Number of errors of a kind created at a code location
+#[derive(Debug, Clone, Copy)]
+pub struct ErrOrigin<K: ErrKind> {
+    pub at: &'static Location<'static>,
+    pub kind: K,
+
+    pub count: u64,
+}
+
+
+/// Error counters at the moment `ErrMetrics::snapshot()` was called
+#[derive(Debug, Clone)]
+pub struct ErrMetricsSnapshot<K: ErrKind> {
+
+    /// Number of all errors
+    pub total: u64,
+
+    /// Number of errors of each kind, ordered by kind code
+    pub by_kind: Vec<(K, u64)>,
+
+    /// Locations and kinds the most errors were created with, most frequent first
+    pub top_origins: Vec<ErrOrigin<K>>,
+
+    /// Errors not accounted in `by_kind`
+    pub untracked: u64,
+
+    /// Errors whose origin location was not tracked because the location table was full
+    pub untracked_origins: u64,
+}
+
+
+impl<K: ErrKind> ErrMetricsSnapshot<K> {
+
+    /// Number of errors of `kind`
+    pub fn count(&self, kind: K) -> u64 {
+        self.by_kind.iter()
+            .find(|(k, _)| k.to_code() == kind.to_code())
+            .map_or(0, |(_, count)| *count)
+    }
+
+    /// Renders the counters in Prometheus text exposition format, metric names start with `prefix`:
+    ///
+    /// ```text
+    /// # HELP app_errors_total Number of errors by kind
+    /// # TYPE app_errors_total counter
+    /// app_errors_total{kind="InvalidData",code="12"} 5
+    /// ```
+    pub fn to_prometheus(&self, prefix: &str) -> String {
+        let mut s = String::new();
+
+        let _ = writeln!(s, "# HELP {}_errors_total Number of errors by kind", prefix);
+        let _ = writeln!(s, "# TYPE {}_errors_total counter", prefix);
+        for (kind, count) in &self.by_kind {
+            let _ = writeln!(s, "{}_errors_total{{kind=\"{}\",code=\"{}\"}} {}",
+                             prefix, kind.name(), kind.to_code(), count);
+        }
+
+        let _ = writeln!(s, "# HELP {}_errors_untracked_total Number of errors not counted by kind", prefix);
+        let _ = writeln!(s, "# TYPE {}_errors_untracked_total counter", prefix);
+        let _ = writeln!(s, "{}_errors_untracked_total {}", prefix, self.untracked);
+
+        let _ = writeln!(s, "# HELP {}_error_origins_total Number of errors by origin location and kind (top {})",
+                         prefix, self.top_origins.len());
+        let _ = writeln!(s, "# TYPE {}_error_origins_total counter", prefix);
+        for origin in &self.top_origins {
+            let _ = writeln!(s, "{}_error_origins_total{{kind=\"{}\",file=\"{}\",line=\"{}\"}} {}",
+                             prefix, origin.kind.name(), escape_label(origin.at.file()), origin.at.line(),
+                             origin.count);
+        }
+        s
+    }
+}
+
+
+/// Escapes a Prometheus label value
+fn escape_label(val: &str) -> String {
+    val.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+
+static BUILTIN_ERR_METRICS: ErrMetrics = ErrMetrics::new();
+
+static ERR_METRICS: AtomicPtr<ErrMetrics> = AtomicPtr::new(ptr::null_mut());
+
+
+/// Installs `metrics` as the process-wide error metrics registry, replacing the previous one
+pub fn set_err_metrics(metrics: &'static ErrMetrics) {
+    ERR_METRICS.store(metrics as *const ErrMetrics as *mut ErrMetrics, Ordering::Release);
+}
+
+
+/// Installs the built-in error metrics registry and returns it
+pub fn install_err_metrics() -> &'static ErrMetrics {
+    set_err_metrics(&BUILTIN_ERR_METRICS);
+    &BUILTIN_ERR_METRICS
+}
+
+
+/// Returns the installed error metrics registry, if any
+pub fn err_metrics() -> Option<&'static ErrMetrics> {
+    let metrics = ERR_METRICS.load(Ordering::Acquire);
+    // SAFETY: only `&'static ErrMetrics` values are stored by `set_err_metrics()`
+    unsafe { metrics.as_ref() }
+}
+
+
+/// Reports a new error to the installed registry; costs one atomic load if none is installed
+#[inline]
+pub(crate) fn report_err(code: u32, at: &'static Location<'static>) {
+    if let Some(metrics) = err_metrics() {
+        metrics.record(code, at);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{set_err_metrics, ErrMetrics};
+    use crate::err::{AppErr, AppErrs, AppResult, AppResultExt};
+
+    crate::declare_app_errors!(TestErr, TestErrTable,
+        TokenExpired, "token expired"
+    );
+
+    // A code no other test uses, the global registry counts errors of all tests
+    crate::declare_app_errors!(CountedErr, CountedErrTable,
+        CountedOnce = 1900, "counted once"
+    );
+
+    static METRICS: ErrMetrics = ErrMetrics::new();
+
+    #[test]
+    fn counts_by_kind_and_origin() {
+        // Not installed globally: other tests create errors concurrently
+        let here = std::panic::Location::caller();
+        for _ in 0..3 {
+            METRICS.record(TestErr::TokenExpired.to_code(), here);
+        }
+        let e: AppErr<TestErr> = crate::app_err!(TestErr::InvalidData, None);
+        METRICS.record(TestErr::InvalidData.to_code(), e.origin().unwrap());
+        METRICS.record(77, std::panic::Location::caller());
+        // Another kind at the same location is a separate origin
+        METRICS.record(TestErr::NotFound.to_code(), here);
+
+        let snapshot = METRICS.snapshot::<TestErr>(2);
+        assert_eq!(snapshot.total, 6);
+        assert_eq!(snapshot.count(TestErr::TokenExpired), 3);
+        assert_eq!(snapshot.count(TestErr::InvalidData), 1);
+        assert_eq!(snapshot.untracked, 1);
+        assert_eq!(snapshot.top_origins.len(), 2);
+        assert_eq!((snapshot.top_origins[0].kind, snapshot.top_origins[0].count), (TestErr::TokenExpired, 3));
+        assert_eq!(snapshot.top_origins[1].count, 1);
+
+        let text = snapshot.to_prometheus("app");
+        assert!(text.contains("app_errors_total{kind=\"TokenExpired\",code=\"1000\"} 3\n"));
+        assert!(text.contains(&format!("app_error_origins_total{{kind=\"TokenExpired\",file=\"{}\",line=\"{}\"}} 3\n",
+                                       here.file(), here.line())));
+    }
+
+    #[test]
+    fn wrapped_error_is_counted_once() {
+        static GLOBAL: ErrMetrics = ErrMetrics::new();
+        set_err_metrics(&GLOBAL);
+
+        let e: AppErr<CountedErr> = crate::app_err!(CountedErr::CountedOnce, None);
+        let r: AppResult<(), CountedErr> = Err::<(), _>(e).ctx("wrapped");
+        assert_eq!(r.unwrap_err().kind, CountedErr::CountedOnce);
+        assert_eq!(GLOBAL.snapshot::<CountedErr>(0).count(CountedErr::CountedOnce), 1);
+
+        let errs: AppErrs<CountedErr> = (0..2).map(|_| crate::app_err!(CountedErr::CountedOnce, None)).collect();
+        assert_eq!(AppErr::from(errs).kind, CountedErr::CountedOnce);
+        assert_eq!(GLOBAL.snapshot::<CountedErr>(0).count(CountedErr::CountedOnce), 3);
+    }
+}
@@ -1,8 +1,55 @@
 
-
 #[allow(unused)]
 use const_format::formatcp;
 
+use std::fmt;
+use std::error::Error;
+use std::panic::Location;
+
+mod builtin;
+mod declare;
+mod ext;
+pub use ext::AppResultExt;
+mod multi;
+pub use multi::{AppErrs, collect_errors};
+mod metrics;
+pub use metrics::{ErrMetrics, ErrMetricsSnapshot, ErrOrigin, ERR_METRICS_MAX_KINDS, ERR_METRICS_MAX_SITES,
+                  set_err_metrics, install_err_metrics, err_metrics};
+mod assert;
+#[doc(hidden)]
+pub use assert::__assert_failure_report;
+mod panic;
+pub use panic::{PanicErr, CatchUnwind, install_panic_hook, catch_unwind, default_crash_report_path,
+                write_crash_report, crash_report_json};
+pub use builtin::{BuiltinKind, BUILTIN_KINDS};
+mod builtin_kinds;
+pub use builtin_kinds::USER_ERR_CODE_BASE;
+mod trace;
+pub use trace::{CodeTrace, CODE_TRACE_DEFAULT_MAX_LEN, set_code_trace_max_len, code_trace_max_len};
+
+#[cfg(feature = "fluent")]
+mod l10n;
+#[cfg(feature = "fluent")]
+pub use l10n::{Loader, LanguageIdentifier, ERR_MSG_ID_PREFIX, err_msg_id, app_err_desc_lang};
+
+#[cfg(feature = "log")]
+mod log_impl;
+#[cfg(feature = "log")]
+pub use log_impl::severity_log_level;
+
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "serde")]
+pub use serde_impl::{AppErrRecord, serialize_kind, deserialize_kind};
+
+/// No-op when the `serde` feature is disabled
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __impl_err_kind_serde {
+    ( $EnumIdent:ident ) => {};
+}
+
 
 /// Compile-time formatted code location
 #[macro_export]
@@ -22,286 +69,438 @@ macro_rules! code_location_proxy {
 }
 
 
-/// Macro that creates app-specific error enum and corresponding error description lookup table;
-#[macro_export]
-macro_rules! declare_app_errors {
-    ( $EnumIdent:ident, $EnumDescIdent:ident, $( $EnumElem:ident, $EnumDescStr:expr ),+ ) => {
-
-        #[derive(Debug, Copy, Clone)]
-        #[allow(unused)]
-        #[repr(u32)]
-        pub enum $EnumIdent {  // error enum
-            // Include std::io::ErrorKind at the beginning;
-            // !Order matters, change it only along with the error description table below!
-            NotFound,
-            PermissionDenied,
-            ConnectionRefused,
-            ConnectionReset,
-            ConnectionAborted,
-            NotConnected,
-            AddrInUse,
-            AddrNotAvailable,
-            BrokenPipe,
-            AlreadyExists,
-            WouldBlock,
-            InvalidInput,
-            InvalidData,
-            TimedOut,
-            WriteZero,
-            Interrupted,
-            Other,
-            UnexpectedEof,
-
-            // Other error kinds
-            FromOtherError,
-
-            // User-defined error kinds
-            $(
-                $EnumElem,
-            )*
-        }
+/// Trait implemented by app-specific error kind enums (see `declare_app_errors!`);
+///
+/// Allows `AppErr`, `LwErr` and helper functions to be shared between apps
+/// that declare different sets of error kinds.
+pub trait ErrKind: fmt::Debug + Copy + Send + Sync + 'static {
+
+    /// Human-readable description of this error kind
+    fn desc(&self) -> &'static str;
+
+    /// Translates `std::io::ErrorKind` into the corresponding error kind;
+    /// `std::io::ErrorKind` values that have no counterpart become `Other`.
+    fn from_io_kind(kind: std::io::ErrorKind) -> Self;
+
+    /// Error kind used for errors that have no better translation (`FromOtherError`)
+    fn from_other_error() -> Self;
+
+    /// Error kind used for panics caught by `apptools::err` panic helpers (`Panicked`)
+    fn panicked() -> Self {
+        Self::from_other_error()
+    }
+
+    /// Name of the enum variant, e.g. `"NotFound"`
+    fn name(&self) -> &'static str;
+
+    /// Looks up the error kind by its variant name
+    fn from_name(name: &str) -> Option<Self>;
+
+    /// Stable numeric error code (`#[repr(u32)]` discriminant)
+    fn to_code(&self) -> u32;
+
+    /// Decodes error kind from its numeric code
+    fn from_code(code: u32) -> Option<Self>;
+
+    /// Category of the error kind
+    fn category(&self) -> ErrCategory;
+
+    /// Severity of the error kind, can be used to pick a log level
+    fn severity(&self) -> ErrSeverity;
+
+    /// True if the failed operation may succeed when retried (e.g. `TimedOut`, `WouldBlock`)
+    fn is_retryable(&self) -> bool;
+}
+
+
+/// Category of an error kind
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ErrCategory {
+    General,
+    Io,
+    Network,
+    Data,
+    Security,
+    Resource,
+    Config,
+    Internal,
+}
+
+
+/// Severity of an error kind, ordered from the least to the most severe
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrSeverity {
+    Debug,
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+
+/// Error returned when a numeric code doesn't correspond to any error kind
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UnknownErrCode(pub u32);
+
+
+impl fmt::Display for UnknownErrCode {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown error code {}", self.0)
+    }
+}
 
-        #[allow(unused)]
-        #[allow(non_upper_case_globals)]
-        pub const $EnumDescIdent: &[&str] = &[  // error description table
-
-            // NotFound
-            "an entity (possibly a file) could not be found",
-
-            // PermissionDenied
-            "operation lacked necessary privileges to complete",
-
-            // ConnectionRefused
-            "connection was refused by the remote server",
-
-            // ConnectionReset
-            "connection was reset by the remote server",
-
-            // ConnectionAborted
-            "connection was aborted (terminated) by the remote server",
-
-            // NotConnected
-            "network operation failed because it was not connected yet",
-
-            // AddrInUse
-            "socket address could not be bound because the address is already in use elsewhere", 
-            
-            // AddrNotAvailable
-            "nonexistent interface was requested or the requested address was not local",
-            
-            // BrokenPipe
-            "operation failed because a pipe was closed",
-            
-            // AlreadyExists
-            "entity (possibly a file) already exists",
-
-            // WouldBlock
-            "operation needs to block to complete, but the blocking operation was requested to not occur", 
-            
-            // InvalidInput
-            "parameter was incorrect",  
-            
-            // InvalidData
-            "data not valid for the operation were encountered",
-            
-            // TimedOut
-            "I/O operation's timeout expired, causing it to be canceled",
-            
-            // WriteZero
-            "operation could not be completed because a call to write returned Ok(0)",
-            
-            // Interrupted
-            "operation was interrupted",
-
-            // Other
-            "",
-
-            // UnexpectedEof
-            "operation could not be completed because 'end of file' was reached prematurely",
-
-            // FromOther
-            "",  // occurred due to another error
-
-            // Append user-defined error descriptions (the order will exactly match the enum order)
-            $(
-                $EnumDescStr,
-            )*
-        ];
 
+impl Error for UnknownErrCode {}
 
-        #[allow(unused)]
-        #[derive(Debug)]
-        pub struct AppErr {  // 80 bytes on 64-bit machine
 
-            /// Error kind enum
-            pub kind: $EnumIdent,  // 4 bytes
+/// Boxed thread-safe error, used as the `source` of `AppErr`
+pub type BoxedErr = Box<dyn Error + Send + Sync>;
 
-            /// Platform-specific error code
-            pub code: Option<i32>,  // 8 bytes
-            pub at: String,  // 24 bytes
-            pub msg: Option<String>,  // 24 bytes
-            pub source: Option<Box<dyn std::error::Error>>  // 16 bytes
+
+/// Application error, generic over the app-specific error kind enum;
+///
+/// `AppErr` is `Send + Sync`, so `AppResult` can be returned from spawned threads and tokio tasks.
+#[derive(Debug)]
+pub struct AppErr<K: ErrKind> {  // 88 bytes on 64-bit machine
+
+    /// Error kind enum
+    pub kind: K,  // 4 bytes
+
+    /// Platform-specific error code
+    pub code: Option<i32>,  // 8 bytes
+
+    /// Code locations where the error was created and propagated through
+    pub at: CodeTrace,  // 32 bytes
+    pub msg: Option<String>,  // 24 bytes
+    pub source: Option<BoxedErr>  // 16 bytes
+}
+
+
+// Compile-time check that `AppErr` stays thread-safe for any error kind
+#[allow(unused)]
+fn assert_app_err_send_sync<K: ErrKind>() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<AppErr<K>>();
+}
+
+
+/// Light-weight error definition;
+///
+/// Never allocates, so it is suitable for hot paths (e.g. per-request loops);
+/// convert it into `AppErr` at the boundaries with `succ_lw!` or `.into()`.
+#[derive(Debug, Copy, Clone)]
+pub struct LwErr<K: ErrKind> {  // 24 bytes on 64-bit machine
+    pub kind: K,  // 4 bytes + align 4 bytes
+    pub code: Option<i32>,  // 8 bytes
+    pub at: &'static Location<'static>,  // 8 bytes
+}
+
+
+/// Generic AppResult type; apps usually alias it with their own error kind enum
+pub type AppResult<T, K> = Result<T, AppErr<K>>;
+
+/// Generic result type for light-weight errors
+pub type LwResult<T, K> = Result<T, LwErr<K>>;
+
+
+/// Returns description of the error kind
+pub fn app_err_desc<K: ErrKind>(e: &K) -> &'static str {
+    e.desc()
+}
+
+
+/// `{}` formats this error only, its source is reachable through `Error::source()`;
+/// `{:#}` appends the whole causal chain, similar to `anyhow::Error`.
+impl<K: ErrKind> fmt::Display for AppErr<K> {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} at {}: {}", &self.kind, &self.at, app_err_desc(&self.kind))?;
+        if let Some(m) = &self.msg {
+            write!(f, ", [{}]", m)?;
+        }
+        if f.alternate() {
+            for cause in self.chain().skip(1) {
+                write!(f, ", caused by: {}", cause)?;
+            }
         }
+        Ok(())
+    }
+}
 
 
-        // Light-weight error definition
-        #[allow(unused)]
-        #[derive(Debug, Copy, Clone)]
-        pub struct LwErr {  // 24 bytes on 64-bit machine
-            pub kind: $EnumIdent,  // 4 bytes + align 4 bytes
-            pub code: Option<i32>,  // 8 bytes
-            pub at: &'static &'static str,  // reference to static string: 8 bytes
+impl<K: ErrKind> Error for AppErr<K> {
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.source {
+            Some(src) => Some(src.as_ref()),
+            None => None,
         }
+    }
+}
+
+
+/// Iterator over an error and its sources, see `AppErr::chain()`
+#[derive(Clone)]
+pub struct Chain<'a> {
+    next: Option<&'a (dyn Error + 'static)>,
+}
+
 
-        // Custom AppResult type
-        pub type AppResult<T> = Result<T, AppErr>;
+impl<'a> Chain<'a> {
+
+    /// Creates a chain that starts with `head` and follows `Error::source()`
+    pub fn new(head: &'a (dyn Error + 'static)) -> Chain<'a> {
+        Chain { next: Some(head) }
+    }
+}
 
 
-        #[allow(unused)]
-        pub fn app_err_desc(e: &$EnumIdent) -> &'static str {
-            let i = *e as usize;
-            assert!(i < $EnumDescIdent.len());
-            $EnumDescIdent[i]
+impl<'a> Iterator for Chain<'a> {
+    type Item = &'a (dyn Error + 'static);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cur = self.next?;
+        self.next = cur.source();
+        Some(cur)
+    }
+}
+
+
+impl<K: ErrKind> AppErr<K> {  // implement some convenience methods
+
+    pub fn new(kind: K, at: &'static Location<'static>, msg: Option<String>) -> AppErr<K> {
+        metrics::report_err(kind.to_code(), at);
+        AppErr {
+            kind,
+            code: None,
+            at: CodeTrace::new(at),
+            msg,
+            source: None,
         }
-        
-        
-        #[allow(unused)]
-        impl std::fmt::Display for AppErr {
-            
-            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-                match &self.msg {
-                    Some(m) =>  match &self.source {
-                                    // user msg and source
-                                    Some(src) => write!(f, "{:?} at {}: {}, [{}], source: {:?}", &self.kind, &self.at, 
-                                                        app_err_desc(&self.kind), &self.msg.as_ref().unwrap(), &self.source.as_ref().unwrap()),
-                                    // user msg, no source
-                                    None => write!(f, "{:?} at {}: {}, [{}]", &self.kind, &self.at, 
-                                                    app_err_desc(&self.kind), &self.msg.as_ref().unwrap()),
-                                }
-                    None => match &self.source {
-                                // no user msg but source
-                                Some(src) => write!(f, "{:?} at {}: {}, source: {:?}", &self.kind, &self.at, 
-                                                    app_err_desc(&self.kind), &self.source.as_ref().unwrap()),
-                                // no user msg, no source
-                                None => write!(f, "{:?} at {}: {}", &self.kind, &self.at, app_err_desc(&self.kind))
-                            }
-                }
-            }
+    }
+
+    pub fn from_other(kind: K, at: &'static Location<'static>, msg: Option<String>,
+                      source: BoxedErr) -> AppErr<K> {
+        metrics::report_err(kind.to_code(), at);
+        AppErr::from_counted(kind, at, msg, source)
+    }
+
+    /// Same as `from_other()` but not reported to error metrics,
+    /// for sources that were counted already, e.g. a wrapped `AppErr`
+    pub fn from_counted(kind: K, at: &'static Location<'static>, msg: Option<String>,
+                        source: BoxedErr) -> AppErr<K> {
+
+        // Try getting platform-specific code if any
+        let code = source.downcast_ref::<std::io::Error>().and_then(|e| e.raw_os_error());
+
+        AppErr {
+            kind,
+            code,
+            at: CodeTrace::new(at),
+            msg,
+            source: Some(source),
+        }
+    }
+
+    pub fn from_std(at: &'static Location<'static>, msg: Option<String>, source: std::io::Error) -> AppErr<K> {
+        let kind = K::from_io_kind(source.kind());
+        metrics::report_err(kind.to_code(), at);
+        AppErr {
+            kind,
+            code: source.raw_os_error(),
+            at: CodeTrace::new(at),
+            msg,
+            source: Some(source.into()),
         }
+    }
+
+    /// Brief error description as String
+    pub fn brief(&self) -> String {
+        let mut s = format!("{:?} at {}", &self.kind, &self.at);
+        match (&self.msg, &self.source) {
+            // user msg and source
+            (Some(m), Some(src)) => s += &format!(": [{}], source: {:?}", m, src),
+            // user msg and no source
+            (Some(m), None) => s += &format!(": [{}]", m),
+            // no user msg but source
+            (None, Some(src)) => s += &format!(": source: {:?}", src),
+            // no user msg and no source
+            (None, None) => {}
+        }
+        s
+    }
+
+    /// Description of the error kind in the requested language, see `app_err_desc_lang()`
+    #[cfg(feature = "fluent")]
+    pub fn desc_lang<L: Loader>(&self, loader: &L, lang: &LanguageIdentifier) -> std::borrow::Cow<'static, str> {
+        app_err_desc_lang(&self.kind, loader, lang)
+    }
+
+    /// Category of the error kind
+    pub fn category(&self) -> ErrCategory {
+        self.kind.category()
+    }
+
+    /// Severity of the error kind
+    pub fn severity(&self) -> ErrSeverity {
+        self.kind.severity()
+    }
+
+    /// True if the failed operation may succeed when retried
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
+    }
+
+    /// Iterates over this error and all of its nested sources (`AppErr`, `std::io::Error`, etc.);
+    /// the first item is the error itself.
+    pub fn chain(&self) -> Chain<'_> {
+        Chain::new(self)
+    }
+
+    /// Returns the innermost error of the causal chain
+    pub fn root_cause(&self) -> &(dyn Error + 'static) {
+        self.chain().last().unwrap_or(self)
+    }
+
+    /// Location where the error was created
+    pub fn origin(&self) -> Option<&'static Location<'static>> {
+        self.at.origin()
+    }
+
+    /// Iterates over code locations the error was created at and propagated through
+    pub fn trace(&self) -> impl Iterator<Item = &'static Location<'static>> + '_ {
+        self.at.iter()
+    }
+
+    // TODO: implement as a trait
+    /// Append proxy code location to the `at` trace
+    #[inline]
+    pub fn append_code_loc(&mut self, proxy_at: &'static Location<'static>) {
+        self.at.push(proxy_at);
+    }
 
+}
 
-        impl AppErr  {  // implement some convenience methods
 
-            #[allow(unused)]
-            pub fn new(kind: $EnumIdent, at: &str, msg: Option<String>) -> AppErr {
-                AppErr {
-                    kind: kind,
-                    code: None,
-                    at: at.to_owned(),
-                    msg: msg,
-                    source: None,
-                }
-            }
-        
-            #[allow(unused)]
-            pub fn from_other(kind: $EnumIdent, at: &str, msg: Option<String>, 
-                              source: Box<dyn std::error::Error>) -> AppErr {
-
-                // Try getting platform-specific code if any
-                let mut other_code = None;
-                if let Some(e) = source.downcast_ref::<std::io::Error>() {
-                    other_code = e.raw_os_error();
-                } 
-
-                AppErr {
-                    at: at.to_owned(),
-                    kind: kind,
-                    code: other_code,
-                    msg: msg,
-                    source: Some(source),
-                }
-            }
+impl<K: ErrKind> LwErr<K> {
+
+    /// Creates a new light-weight error; `at` is usually `Location::caller()`
+    #[inline]
+    pub fn new(kind: K, at: &'static Location<'static>) -> LwErr<K> {
+        LwErr { kind, code: None, at }
+    }
+
+    /// Sets platform-specific error code
+    #[inline]
+    pub fn with_code(mut self, code: i32) -> LwErr<K> {
+        self.code = Some(code);
+        self
+    }
+
+    /// True if the failed operation may succeed when retried
+    #[inline]
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
+    }
+}
+
+
+impl<K: ErrKind> fmt::Display for LwErr<K> {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} at {}:{}: {}", &self.kind, self.at.file(), self.at.line(), app_err_desc(&self.kind))?;
+        if let Some(code) = self.code {
+            write!(f, " (code {})", code)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl<K: ErrKind> Error for LwErr<K> {}
+
+
+impl<K: ErrKind> From<LwErr<K>> for AppErr<K> {
+
+    /// Lossless conversion: kind, code and location are preserved
+    fn from(e: LwErr<K>) -> Self {
+        AppErr {
+            kind: e.kind,
+            code: e.code,
+            at: CodeTrace::new(e.at),
+            msg: None,
+            source: None,
+        }
+    }
+}
 
-            #[allow(unused)]
-            pub fn from_std(at: &str, msg: Option<String>, 
-                            source: std::io::Error) -> AppErr {
-
-                use std::io::ErrorKind;
-
-                let translated_kind = match (source.kind()) {
-                    ErrorKind::NotFound => ErrList::NotFound,
-                    ErrorKind::PermissionDenied => ErrList::PermissionDenied,
-                    ErrorKind::ConnectionRefused => ErrList::ConnectionRefused,
-                    ErrorKind::ConnectionReset => ErrList::ConnectionReset,
-                    ErrorKind::ConnectionAborted => ErrList::ConnectionAborted,
-                    ErrorKind::NotConnected => ErrList::NotConnected,
-                    ErrorKind::AddrInUse => ErrList::AddrInUse,
-                    ErrorKind::AddrNotAvailable => ErrList::AddrNotAvailable,
-                    ErrorKind::BrokenPipe => ErrList::BrokenPipe,
-                    ErrorKind::AlreadyExists => ErrList::AlreadyExists,
-                    ErrorKind::WouldBlock => ErrList::WouldBlock,
-                    ErrorKind::InvalidInput => ErrList::InvalidInput,
-                    ErrorKind::InvalidData => ErrList::InvalidData,
-                    ErrorKind::TimedOut => ErrList::TimedOut,
-                    ErrorKind::WriteZero => ErrList::WriteZero,
-                    ErrorKind::Interrupted => ErrList::Interrupted,
-                    ErrorKind::Other => ErrList::Other,
-                    ErrorKind::UnexpectedEof => ErrList::UnexpectedEof,
-                    _ => ErrList::Other,
-                };             
-
-                // let code = match(source.raw_os_error()) {
-                //     Some(val) => val,
-                //     None => 0
-                // };   
-
-                AppErr {
-                    at: at.to_owned(),
-                    kind: translated_kind,
-                    code: source.raw_os_error(),
-                    msg: msg,
-                    source: Some(source.into()),
-                }
-            }
 
-            // brief error description as String
-            #[allow(unused)]
-            pub fn brief(&self) -> String {
-                match &self.msg {
-                    Some(m) =>  match &self.source {
-                                    // user msg and source
-                                    Some(src) => format!("{:?} at {}: [{}], source: {:?}", &self.kind, &self.at, 
-                                                        &self.msg.as_ref().unwrap(), &self.source.as_ref().unwrap()),
-                                    // user msg and no source
-                                    None => format!("{:?} at {}: [{}]", &self.kind, &self.at, 
-                                                    &self.msg.as_ref().unwrap()),
-                                }
-                    None => match &self.source {
-                                // no user msg but source
-                                Some(src) => format!("{:?} at {}: source: {:?}", &self.kind, &self.at, 
-                                                    &self.source.as_ref().unwrap()),
-                                // no user msg and no source
-                                None => format!("{:?} at {}", &self.kind, &self.at)
-                            }
+impl<K: ErrKind> From<std::io::Error> for AppErr<K> {
+
+    /// Allows plain `?` on `std::io::Error`; the kind is translated from `std::io::ErrorKind`
+    /// and `at` is the location of `?`.
+    #[track_caller]
+    fn from(source: std::io::Error) -> Self {
+        AppErr::from_std(Location::caller(), None, source)
+    }
+}
+
+
+impl<K: ErrKind> From<BoxedErr> for AppErr<K> {
+
+    /// Allows plain `?` on boxed errors, the kind is `FromOtherError`
+    #[track_caller]
+    fn from(source: BoxedErr) -> Self {
+        AppErr::from_other(K::from_other_error(), Location::caller(), None, source)
+    }
+}
+
+
+/// Implements `From<$ErrType> for AppErr<K>` for foreign errors,
+/// `$IoKind` is translated into the app error kind via `ErrKind::from_io_kind()`.
+/// Also generates `foreign_io_kind()`, so `AppResultExt` maps these errors the same way.
+///
+/// Note: these impls can't be generated by `declare_app_errors!` in the user crate
+/// because of the orphan rules, so they are generic over `K` instead.
+macro_rules! impl_from_error {
+    ( $( $ErrType:ty => $IoKind:ident ),+ $(,)? ) => {
+        $(
+            impl<K: ErrKind> From<$ErrType> for AppErr<K> {
+
+                #[track_caller]
+                fn from(source: $ErrType) -> Self {
+                    AppErr::from_other(K::from_io_kind(std::io::ErrorKind::$IoKind), Location::caller(),
+                                       None, source.into())
                 }
             }
+        )+
 
-            // TODO: implement as a trait
-            // Append proxy code location to the `at` field
-            #[allow(unused)]
-            #[inline]
-            pub fn append_code_loc(&mut self, proxy_at: &str) {
-                match self {
-                    AppErr {kind: _, code: _, at, msg: _, source: _} => *at += proxy_at,
-                    _ => return
+        /// `std::io::ErrorKind` that the `From` impl of a foreign error assigns, if it has one
+        pub(crate) fn foreign_io_kind(source: &BoxedErr) -> Option<std::io::ErrorKind> {
+            $(
+                if source.is::<$ErrType>() {
+                    return Some(std::io::ErrorKind::$IoKind);
                 }
-            }
-        
+            )+
+            None
         }
-
     };
 }
 
+impl_from_error!(
+    std::num::ParseIntError => InvalidData,
+    std::num::ParseFloatError => InvalidData,
+    std::num::TryFromIntError => InvalidData,
+    std::str::ParseBoolError => InvalidData,
+    std::str::Utf8Error => InvalidData,
+    std::string::FromUtf8Error => InvalidData,
+    std::net::AddrParseError => InvalidInput,
+    std::time::SystemTimeError => Other,
+    std::fmt::Error => Other,
+);
+
+
 // TODO: create trait ProxyCodeLocation
 // Similar to `try!` but can be used only with AppErr error type.
 // If code inside this macro fails, the error will be forwarded to the callee,
@@ -309,7 +508,7 @@ macro_rules! declare_app_errors {
 #[macro_export]
 macro_rules! succ {
     ( $x:expr ) => {
-        $x.map_err(|mut e| { e.append_code_loc(&apptools::code_location_proxy!()); e } )?
+        $x.map_err(|mut e| { e.append_code_loc(std::panic::Location::caller()); e } )?
     };
 }
 
@@ -317,7 +516,7 @@ macro_rules! succ {
 #[macro_export]
 macro_rules! app_err {
     ( $kind: expr, $msg:expr ) => {
-        AppErr::new($kind, apptools::code_location!(), $msg)
+        $crate::err::AppErr::new($kind, std::panic::Location::caller(), $msg)
     };
 }
 
@@ -325,16 +524,16 @@ macro_rules! app_err {
 #[macro_export]
 macro_rules! neg_result {
     ( $kind: expr, $msg:expr ) => {
-        Err(AppErr::new($kind, apptools::code_location!(), $msg))
+        Err($crate::err::AppErr::new($kind, std::panic::Location::caller(), $msg))
     };
 }
 
-/// Create a new AppErr from another error that implements 
+/// Create a new AppErr from another error that implements
 /// `std::error::Error` trait, add an optional message of type Option<String>.
 #[macro_export]
 macro_rules! app_err_from_other {
     ( $kind: expr, $msg:expr, $source: expr ) => {
-        AppErr::from_other($kind, apptools::code_location!(), $msg, $source.into())
+        $crate::err::AppErr::from_other($kind, std::panic::Location::caller(), $msg, $source.into())
     };
 }
 
@@ -343,31 +542,51 @@ macro_rules! app_err_from_other {
 #[macro_export]
 macro_rules! neg_result_from_err {
     ( $kind: expr, $msg:expr, $source: expr ) => {
-        Err(AppErr::from_other($kind, apptools::code_location!(), $msg, $source.into()))
+        Err($crate::err::AppErr::from_other($kind, std::panic::Location::caller(), $msg, $source.into()))
     };
 }
 
 
-// Create a new AppErr from another error that implements 
-// `std::error::Error` trait, add an optional message of type Option<String>.
-// #[macro_export]
-// macro_rules! app_err_from_std {
-//     ( $kind: expr, $msg:expr, $source: expr ) => {
-//         match ($kind) {
+/// Create a new AppErr from `std::io::Error`, the error kind is translated
+/// from `std::io::ErrorKind`; add an optional message of type Option<String>.
+#[macro_export]
+macro_rules! app_err_from_std {
+    ( $msg:expr, $source: expr ) => {
+        $crate::err::AppErr::from_std(std::panic::Location::caller(), $msg, $source)
+    };
+}
 
-//         }
-//         AppErr::from_std($kind, apptools::code_location!(), $msg, $source)
-//     };
-// }
+
+/// Create a new light-weight error (`LwErr`), no allocation occurs
+#[macro_export]
+macro_rules! lw_err {
+    ( $kind: expr ) => {
+        $crate::err::LwErr::new($kind, std::panic::Location::caller())
+    };
+}
 
 
-/// Create a new AppErr from another error that implements 
-/// `std::error::Error` trait, add an optional message of type Option<String>.
+/// Create a new `LwErr` and wrap it into `LwResult`
 #[macro_export]
-macro_rules! app_err_from_std {
-    ( $msg:expr, $source: expr ) => {
-            
-      AppErr::from_std(apptools::code_location!(), $msg, $source)
-        
+macro_rules! lw_neg_result {
+    ( $kind: expr ) => {
+        Err($crate::err::LwErr::new($kind, std::panic::Location::caller()))
+    };
+}
+
+
+/// Similar to `succ!`, but for expressions that return `LwResult`
+/// inside functions that return `AppResult`: `LwErr` is converted into `AppErr`
+/// and this proxy code location is appended.
+///
+/// Within hot paths returning `LwResult`, use plain `?` to propagate `LwErr` without allocation.
+#[macro_export]
+macro_rules! succ_lw {
+    ( $x:expr ) => {
+        $x.map_err(|e| {
+            let mut e = $crate::err::AppErr::from(e);
+            e.append_code_loc(std::panic::Location::caller());
+            e
+        } )?
     };
 }
@@ -0,0 +1,239 @@
+
+// Collection of errors for batch operations
+
+use std::error::Error;
+use std::fmt;
+use std::iter::FromIterator;
+use std::panic::Location;
+
+use super::{AppErr, AppResult, ErrKind};
+
+
+/// Multiple errors collected from a batch operation, e.g. validation of a config file;
+///
+/// `{}` renders a summary grouped by kind, `{:#}` also lists every error.
+/// `succ!` appends the propagation site to each collected error, `?` converts `AppErrs`
+/// into a single `AppErr` (the collection becomes its source).
+#[derive(Debug)]
+pub struct AppErrs<K: ErrKind> {
+    errs: Vec<AppErr<K>>,
+}
+
+
+impl<K: ErrKind> AppErrs<K> {
+
+    pub fn new() -> AppErrs<K> {
+        AppErrs { errs: Vec::new() }
+    }
+
+    pub fn push(&mut self, e: AppErr<K>) {
+        self.errs.push(e);
+    }
+
+    pub fn len(&self) -> usize {
+        self.errs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errs.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, AppErr<K>> {
+        self.errs.iter()
+    }
+
+    pub fn into_vec(self) -> Vec<AppErr<K>> {
+        self.errs
+    }
+
+    /// Returns `Ok(val)` if no errors were collected, `Err(self)` otherwise
+    pub fn into_result<T>(self, val: T) -> Result<T, AppErrs<K>> {
+        if self.errs.is_empty() {
+            Ok(val)
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Number of errors of each kind, in the order kinds first occur
+    pub fn count_by_kind(&self) -> Vec<(K, usize)> {
+        let mut counts: Vec<(K, usize)> = Vec::new();
+        for e in &self.errs {
+            // Compared by code: kinds are not required to implement `PartialEq`
+            match counts.iter_mut().find(|(k, _)| k.to_code() == e.kind.to_code()) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((e.kind, 1)),
+            }
+        }
+        counts
+    }
+
+    /// Renders the summary: `3 errors: InvalidData x2, NotFound x1`
+    pub fn summary(&self) -> String {
+        format!("{}", self)
+    }
+
+    /// Appends proxy code location to the trace of each error, used by `succ!`
+    #[inline]
+    pub fn append_code_loc(&mut self, proxy_at: &'static Location<'static>) {
+        for e in &mut self.errs {
+            e.append_code_loc(proxy_at);
+        }
+    }
+}
+
+
+/// Collects all values, or all errors if at least one item failed
+pub fn collect_errors<T, K, I>(iter: I) -> Result<Vec<T>, AppErrs<K>>
+    where K: ErrKind, I: IntoIterator<Item = AppResult<T, K>> {
+
+    let mut vals = Vec::new();
+    let mut errs = AppErrs::new();
+    for item in iter {
+        match item {
+            Ok(val) if errs.is_empty() => vals.push(val),
+            Ok(_) => {},
+            Err(e) => errs.push(e),
+        }
+    }
+    errs.into_result(vals)
+}
+
+
+impl<K: ErrKind> fmt::Display for AppErrs<K> {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let n = self.errs.len();
+        write!(f, "{} error{}", n, if n == 1 { "" } else { "s" })?;
+        for (i, (kind, count)) in self.count_by_kind().iter().enumerate() {
+            write!(f, "{}{:?} x{}", if i == 0 { ": " } else { ", " }, kind, count)?;
+        }
+        if f.alternate() {
+            for (i, e) in self.errs.iter().enumerate() {
+                write!(f, "\n  {}: {:#}", i, e)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl<K: ErrKind> Error for AppErrs<K> {
+
+    /// The first collected error
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.errs.first().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+
+impl<K: ErrKind> Default for AppErrs<K> {
+
+    fn default() -> Self {
+        AppErrs::new()
+    }
+}
+
+
+impl<K: ErrKind> From<AppErr<K>> for AppErrs<K> {
+
+    fn from(e: AppErr<K>) -> Self {
+        AppErrs { errs: vec![e] }
+    }
+}
+
+
+impl<K: ErrKind> From<AppErrs<K>> for AppErr<K> {
+
+    /// Creates an error of the common kind of all collected errors (or `FromOtherError`),
+    /// the collection becomes its source; it's not counted in error metrics again
+    #[track_caller]
+    fn from(errs: AppErrs<K>) -> Self {
+        let counts = errs.count_by_kind();
+        let kind = match counts.as_slice() {
+            [(kind, _)] => *kind,
+            _ => K::from_other_error(),
+        };
+        AppErr::from_counted(kind, Location::caller(), Some(errs.summary()), Box::new(errs))
+    }
+}
+
+
+impl<K: ErrKind> FromIterator<AppErr<K>> for AppErrs<K> {
+
+    fn from_iter<I: IntoIterator<Item = AppErr<K>>>(iter: I) -> Self {
+        AppErrs { errs: iter.into_iter().collect() }
+    }
+}
+
+
+impl<K: ErrKind> Extend<AppErr<K>> for AppErrs<K> {
+
+    fn extend<I: IntoIterator<Item = AppErr<K>>>(&mut self, iter: I) {
+        self.errs.extend(iter)
+    }
+}
+
+
+impl<K: ErrKind> IntoIterator for AppErrs<K> {
+    type Item = AppErr<K>;
+    type IntoIter = std::vec::IntoIter<AppErr<K>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errs.into_iter()
+    }
+}
+
+
+impl<'a, K: ErrKind> IntoIterator for &'a AppErrs<K> {
+    type Item = &'a AppErr<K>;
+    type IntoIter = std::slice::Iter<'a, AppErr<K>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errs.iter()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{AppErrs, collect_errors};
+    use crate::err::{AppErr, AppResult};
+
+    crate::declare_app_errors!(TestErr, TestErrTable,
+        TokenExpired, "token expired"
+    );
+
+    fn parse_all(items: &[&str]) -> Result<Vec<u32>, AppErrs<TestErr>> {
+        let vals = crate::succ!(collect_errors(items.iter().map(|s| -> AppResult<u32, TestErr> { Ok(s.parse()?) })));
+        Ok(vals)
+    }
+
+    #[test]
+    fn collects_all_errors() {
+        assert_eq!(parse_all(&["1", "2"]).unwrap(), vec![1, 2]);
+
+        let errs = parse_all(&["1", "x", "", "-3"]).unwrap_err();
+        assert_eq!(errs.len(), 3);
+        assert!(errs.iter().all(|e| e.at.len() == 2));
+        assert_eq!(errs.summary(), "3 errors: InvalidData x3");
+
+        let e: AppErr<TestErr> = errs.into();
+        assert_eq!(e.kind, TestErr::InvalidData);
+    }
+
+    #[test]
+    fn summary_is_grouped_by_kind() {
+        let errs: AppErrs<TestErr> = vec![
+            crate::app_err!(TestErr::TokenExpired, None),
+            crate::app_err!(TestErr::NotFound, None),
+            crate::app_err!(TestErr::TokenExpired, None),
+        ].into_iter().collect();
+        assert_eq!(errs.summary(), "3 errors: TokenExpired x2, NotFound x1");
+        assert_eq!(format!("{:#}", errs).lines().count(), 4);
+
+        let e: AppErr<TestErr> = errs.into();
+        assert_eq!(e.kind, TestErr::FromOtherError);
+    }
+}
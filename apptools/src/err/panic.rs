@@ -0,0 +1,264 @@
+
+// Panic to AppErr bridge: panic hook, catch_unwind for futures and crash reports
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::panic::{self, AssertUnwindSafe, Location, PanicHookInfo};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{AppErr, AppResult, ErrKind};
+
+
+/// Details of a panic, the source of an `AppErr` of the `Panicked` kind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicErr {
+
+    /// Panic message if the payload is a string
+    pub msg: String,
+
+    /// Location of the panic as `file:line:column`, if known
+    pub location: Option<String>,
+
+    /// Name of the panicked thread
+    pub thread: Option<String>,
+}
+
+
+impl PanicErr {
+
+    /// Extracts panic details from the panic hook arguments
+    pub fn from_hook_info(info: &PanicHookInfo) -> PanicErr {
+        PanicErr {
+            msg: payload_msg(info.payload()),
+            location: info.location().map(|loc| format!("{}:{}:{}", loc.file(), loc.line(), loc.column())),
+            thread: std::thread::current().name().map(str::to_owned),
+        }
+    }
+
+    /// Extracts panic details from a payload returned by `std::panic::catch_unwind()`;
+    ///
+    /// The location is known only if the panic hook installed by `install_panic_hook()` saw the panic.
+    pub fn from_payload(payload: &(dyn Any + Send)) -> PanicErr {
+        let msg = payload_msg(payload);
+        match LAST_PANIC.with(|last| last.borrow_mut().take()) {
+            Some(last) if last.msg == msg => last,
+            _ => PanicErr {
+                msg,
+                location: None,
+                thread: std::thread::current().name().map(str::to_owned),
+            },
+        }
+    }
+}
+
+
+impl fmt::Display for PanicErr {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "thread '{}' panicked", self.thread.as_deref().unwrap_or("<unnamed>"))?;
+        if let Some(loc) = &self.location {
+            write!(f, " at {}", loc)?;
+        }
+        write!(f, ": {}", self.msg)
+    }
+}
+
+
+impl Error for PanicErr {}
+
+
+fn payload_msg(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&'static str>() {
+        (*s).to_owned()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "Box<dyn Any>".to_owned()
+    }
+}
+
+
+thread_local! {
+    // Details of the latest panic on this thread, taken by `catch_unwind()`
+    static LAST_PANIC: RefCell<Option<PanicErr>> = const { RefCell::new(None) };
+}
+
+
+/// Installs a panic hook that converts each panic into an `AppErr` of the `Panicked` kind;
+///
+/// The previous hook runs first (the default one prints the panic message), then `on_panic` is called
+/// with the error. If `crash_report` is set, the error is also written there as JSON,
+/// see `default_crash_report_path()`. The hook itself never panics.
+#[track_caller]
+pub fn install_panic_hook<K, F>(crash_report: Option<PathBuf>, on_panic: F)
+    where K: ErrKind, F: Fn(&AppErr<K>) + Send + Sync + 'static {
+
+    let at = Location::caller();
+    let prev_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        prev_hook(info);
+
+        let panic_err = PanicErr::from_hook_info(info);
+        let _ = LAST_PANIC.try_with(|last| last.replace(Some(panic_err.clone())));
+
+        let e = AppErr::<K>::from_other(K::panicked(), at, None, Box::new(panic_err));
+        if let Some(path) = &crash_report {
+            if let Err(report_err) = write_crash_report(path, &e) {
+                eprintln!("failed to write crash report '{}': {}", path.display(), report_err);
+            }
+        }
+        on_panic(&e);
+    }));
+}
+
+
+/// Crash report path next to the binary: `<binary path>.crash.json`
+pub fn default_crash_report_path() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    let mut name = exe.file_name()?.to_os_string();
+    name.push(".crash.json");
+    Some(exe.with_file_name(name))
+}
+
+
+/// Writes the error as a JSON crash report
+pub fn write_crash_report<K: ErrKind>(path: &Path, e: &AppErr<K>) -> io::Result<()> {
+    fs::write(path, crash_report_json(e))
+}
+
+
+/// Renders the error as a JSON crash report, does not depend on the `serde` feature
+pub fn crash_report_json<K: ErrKind>(e: &AppErr<K>) -> String {
+    let panic_err = e.source.as_ref().and_then(|src| src.downcast_ref::<PanicErr>());
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let trail: Vec<String> = e.trace().map(|loc| json_str(&format!("{}:{}", loc.file(), loc.line()))).collect();
+    let opt_str = |s: Option<&str>| s.map_or_else(|| "null".to_owned(), json_str);
+
+    format!("{{\"kind\":{},\"code\":{},\"timestamp\":{},\"pid\":{},\"thread\":{},\"panic_msg\":{},\
+             \"panic_location\":{},\"trail\":[{}],\"error\":{}}}\n",
+            json_str(e.kind.name()), e.kind.to_code(), timestamp, std::process::id(),
+            opt_str(panic_err.and_then(|p| p.thread.as_deref())),
+            opt_str(panic_err.map(|p| p.msg.as_str())),
+            opt_str(panic_err.and_then(|p| p.location.as_deref())),
+            trail.join(","), json_str(&format!("{:#}", e)))
+}
+
+
+/// Quotes and escapes a JSON string
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+
+/// Future returned by `catch_unwind()`
+pub struct CatchUnwind<F> {
+    fut: Pin<Box<F>>,
+    at: &'static Location<'static>,
+}
+
+
+/// Runs a future that returns `AppResult`, a panic while polling it becomes an `AppErr` of the
+/// `Panicked` kind created at the caller location:
+///
+/// ```ignore
+/// tokio::task::spawn_local(apptools::err::catch_unwind(async move {
+///     handle_connection(stream).await
+/// }));
+/// ```
+#[track_caller]
+pub fn catch_unwind<T, K, F>(fut: F) -> CatchUnwind<F>
+    where K: ErrKind, F: Future<Output = AppResult<T, K>> {
+    CatchUnwind { fut: Box::pin(fut), at: Location::caller() }
+}
+
+
+impl<T, K, F> Future for CatchUnwind<F>
+    where K: ErrKind, F: Future<Output = AppResult<T, K>> {
+
+    type Output = AppResult<T, K>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let at = self.at;
+        match panic::catch_unwind(AssertUnwindSafe(|| self.fut.as_mut().poll(cx))) {
+            Ok(poll) => poll,
+            Err(payload) => {
+                let panic_err = PanicErr::from_payload(payload.as_ref());
+                Poll::Ready(Err(AppErr::from_other(K::panicked(), at, None, Box::new(panic_err))))
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::future::Future;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    use super::{catch_unwind, crash_report_json, PanicErr};
+    use crate::err::AppResult;
+
+    crate::declare_app_errors!(TestErr, TestErrTable,
+        TokenExpired, "token expired"
+    );
+
+    /// Waker that does nothing, `Waker::noop()` needs Rust 1.85
+    fn noop_waker() -> Waker {
+        const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RawWaker::new(std::ptr::null(), &VTABLE),
+                                                           |_| {}, |_| {}, |_| {});
+        unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) }
+    }
+
+    fn poll_once<F: Future>(fut: F) -> Poll<F::Output> {
+        let mut fut = Box::pin(fut);
+        fut.as_mut().poll(&mut Context::from_waker(&noop_waker()))
+    }
+
+    #[test]
+    fn catch_unwind_converts_panic() {
+        let res: Poll<AppResult<u32, TestErr>> = poll_once(catch_unwind(async { Ok(5) }));
+        assert!(matches!(res, Poll::Ready(Ok(5))));
+
+        async fn check_conns(active_conns: u32) -> AppResult<u32, TestErr> {
+            assert!(active_conns == 0, "too many active conns: {}", active_conns);
+            Ok(active_conns)
+        }
+        let res = poll_once(catch_unwind(check_conns(3)));
+        let e = match res {
+            Poll::Ready(Err(e)) => e,
+            _ => panic!("panic was not caught"),
+        };
+        assert_eq!(e.kind, TestErr::Panicked);
+        assert_eq!(e.origin().unwrap().file(), file!());
+        let panic_err = e.source.as_ref().unwrap().downcast_ref::<PanicErr>().unwrap();
+        assert_eq!(panic_err.msg, "too many active conns: 3");
+
+        let json = crash_report_json(&e);
+        assert!(json.starts_with(r#"{"kind":"Panicked","code":19,"#));
+        assert!(json.contains(r#""panic_msg":"too many active conns: 3""#));
+    }
+}
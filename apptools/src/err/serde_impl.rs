@@ -0,0 +1,197 @@
+
+// Serde support for apptools errors (enabled by the `serde` feature)
+
+use std::error::Error;
+use std::fmt;
+use std::panic::Location;
+
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+
+use super::{AppErr, LwErr, ErrKind, app_err_desc};
+
+
+/// Serializes error kind as its variant name, used by `declare_app_errors!`
+pub fn serialize_kind<K: ErrKind, S: Serializer>(kind: &K, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(kind.name())
+}
+
+
+/// Deserializes error kind from its variant name, used by `declare_app_errors!`
+pub fn deserialize_kind<'de, K: ErrKind, D: Deserializer<'de>>(deserializer: D) -> Result<K, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    K::from_name(&name).ok_or_else(|| serde::de::Error::custom(format!("unknown error kind '{}'", name)))
+}
+
+
+/// Stable wire and log schema of `AppErr` and `LwErr`;
+///
+/// `AppErr` and `LwErr` serialize into this schema directly. Code locations of a received error
+/// are not `'static`, so an error is always deserialized into `AppErrRecord`, which can then
+/// be converted into a local `AppErr` (the record becomes its source).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppErrRecord<K: ErrKind> {
+
+    /// Error kind, serialized as variant name
+    pub kind: K,
+
+    /// Numeric error kind code
+    pub code: u32,
+
+    /// Platform-specific error code
+    pub os_code: Option<i32>,
+
+    /// Code locations the error was created at and propagated through, as `file:line`
+    pub trail: Vec<String>,
+
+    /// User message
+    pub msg: Option<String>,
+
+    /// Stringified source errors, starting from the direct source
+    pub source_chain: Vec<String>,
+}
+
+
+impl<K: ErrKind> From<&AppErr<K>> for AppErrRecord<K> {
+
+    fn from(e: &AppErr<K>) -> Self {
+        AppErrRecord {
+            kind: e.kind,
+            code: e.kind.to_code(),
+            os_code: e.code,
+            trail: e.trace().map(|loc| format!("{}:{}", loc.file(), loc.line())).collect(),
+            msg: e.msg.clone(),
+            source_chain: e.chain().skip(1).map(|src| src.to_string()).collect(),
+        }
+    }
+}
+
+
+impl<K: ErrKind> From<&LwErr<K>> for AppErrRecord<K> {
+
+    fn from(e: &LwErr<K>) -> Self {
+        AppErrRecord {
+            kind: e.kind,
+            code: e.kind.to_code(),
+            os_code: e.code,
+            trail: vec![format!("{}:{}", e.at.file(), e.at.line())],
+            msg: None,
+            source_chain: Vec::new(),
+        }
+    }
+}
+
+
+impl<K: ErrKind + Serialize> Serialize for AppErr<K> {
+
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AppErrRecord::from(self).serialize(serializer)
+    }
+}
+
+
+impl<K: ErrKind + Serialize> Serialize for LwErr<K> {
+
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AppErrRecord::from(self).serialize(serializer)
+    }
+}
+
+
+impl<K: ErrKind> fmt::Display for AppErrRecord<K> {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} at {}: {}", &self.kind, self.trail.join(" -> "), app_err_desc(&self.kind))?;
+        if let Some(m) = &self.msg {
+            write!(f, ", [{}]", m)?;
+        }
+        for src in &self.source_chain {
+            write!(f, ", caused by: {}", src)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl<K: ErrKind> Error for AppErrRecord<K> {}
+
+
+impl<K: ErrKind> From<AppErrRecord<K>> for AppErr<K> {
+
+    /// Creates a local error of the same kind, the received record becomes its source
+    #[track_caller]
+    fn from(record: AppErrRecord<K>) -> Self {
+        let os_code = record.os_code;
+        let mut e = AppErr::from_other(record.kind, Location::caller(), record.msg.clone(), Box::new(record));
+        e.code = os_code;
+        e
+    }
+}
+
+
+/// Implements `Serialize` and `Deserialize` for an error kind enum, used by `declare_app_errors!`
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __impl_err_kind_serde {
+    ( $EnumIdent:ident ) => {
+
+        impl $crate::serde::Serialize for $EnumIdent {
+            fn serialize<S: $crate::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                $crate::err::serialize_kind(self, serializer)
+            }
+        }
+
+        impl<'de> $crate::serde::Deserialize<'de> for $EnumIdent {
+            fn deserialize<D: $crate::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                $crate::err::deserialize_kind(deserializer)
+            }
+        }
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::AppErrRecord;
+    use crate::err::{AppErr, LwErr};
+
+    crate::declare_app_errors!(TestErr, TestErrTable,
+        TokenExpired, "token expired"
+    );
+
+    #[test]
+    fn app_err_json_round_trip() {
+        fn parse_inner() -> Result<u32, AppErr<TestErr>> {
+            Ok("x1".parse::<u32>()?)
+        }
+        fn parse() -> Result<u32, AppErr<TestErr>> {
+            let val = crate::succ!(parse_inner());
+            Ok(val)
+        }
+        let e = parse().unwrap_err();
+        let json = serde_json::to_string(&e).unwrap();
+        let record: AppErrRecord<TestErr> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(record.kind, TestErr::InvalidData);
+        assert_eq!(record.code, TestErr::InvalidData as u32);
+        assert_eq!(record.trail.len(), 2);
+        assert_eq!(record.source_chain, vec!["invalid digit found in string".to_owned()]);
+
+        let local: AppErr<TestErr> = record.clone().into();
+        assert_eq!(local.kind, TestErr::InvalidData);
+        assert_eq!(local.source.unwrap().to_string(), record.to_string());
+    }
+
+    #[test]
+    fn lw_err_json_schema() {
+        let e: LwErr<TestErr> = crate::lw_err!(TestErr::TokenExpired).with_code(7);
+        let val = serde_json::to_value(e).unwrap();
+
+        assert_eq!(val["kind"], "TokenExpired");
+        assert_eq!(val["code"], TestErr::TokenExpired as u32);
+        assert_eq!(val["os_code"], 7);
+        assert_eq!(val["msg"], serde_json::Value::Null);
+        assert!(serde_json::from_str::<AppErrRecord<TestErr>>(r#"{"kind":"NoSuchKind","code":0,
+            "os_code":null,"trail":[],"msg":null,"source_chain":[]}"#).is_err());
+    }
+}
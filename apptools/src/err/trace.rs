@@ -0,0 +1,119 @@
+
+use std::fmt;
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+
+/// Default maximum number of code locations stored in a `CodeTrace`
+pub const CODE_TRACE_DEFAULT_MAX_LEN: usize = 16;
+
+static CODE_TRACE_MAX_LEN: AtomicUsize = AtomicUsize::new(CODE_TRACE_DEFAULT_MAX_LEN);
+
+
+/// Sets the maximum number of code locations stored in each `CodeTrace` (process-wide, at least 1);
+/// locations pushed beyond the limit are only counted.
+pub fn set_code_trace_max_len(max_len: usize) {
+    CODE_TRACE_MAX_LEN.store(max_len.max(1), Ordering::Relaxed);
+}
+
+
+/// Returns the maximum number of code locations stored in each `CodeTrace`
+pub fn code_trace_max_len() -> usize {
+    CODE_TRACE_MAX_LEN.load(Ordering::Relaxed)
+}
+
+
+/// Trail of code locations an error was created at and propagated through;
+///
+/// The first location is the error origin, each `succ!` appends one more location.
+/// Locations are `&'static`, so propagation never formats or copies strings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeTrace {  // 32 bytes on 64-bit machine
+    sites: Vec<&'static Location<'static>>,  // 24 bytes
+
+    /// Number of locations that were not stored because of the length limit
+    omitted: u32,  // 4 bytes + align 4 bytes
+}
+
+
+impl CodeTrace {
+
+    /// Creates a trace that starts at `origin`
+    pub fn new(origin: &'static Location<'static>) -> CodeTrace {
+        let mut sites = Vec::with_capacity(4);
+        sites.push(origin);
+        CodeTrace { sites, omitted: 0 }
+    }
+
+    /// Appends a propagation site; if the trace is full, the site is only counted
+    #[inline]
+    pub fn push(&mut self, site: &'static Location<'static>) {
+        if self.sites.len() < code_trace_max_len() {
+            self.sites.push(site);
+        } else {
+            self.omitted = self.omitted.saturating_add(1);
+        }
+    }
+
+    /// Location where the error was created
+    pub fn origin(&self) -> Option<&'static Location<'static>> {
+        self.sites.first().copied()
+    }
+
+    /// Iterates over stored locations, starting from the origin
+    pub fn iter(&self) -> impl Iterator<Item = &'static Location<'static>> + '_ {
+        self.sites.iter().copied()
+    }
+
+    /// Number of stored locations
+    pub fn len(&self) -> usize {
+        self.sites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sites.is_empty()
+    }
+
+    /// Number of locations that were dropped because of the length limit
+    pub fn omitted(&self) -> usize {
+        self.omitted as usize
+    }
+
+    /// Renders the trace as one line: `a.rs:1 -> b.rs:2`
+    pub fn one_line(&self) -> String {
+        format!("{}", self)
+    }
+
+    /// Renders the trace as multiple lines, one location per line, each prefixed with `indent`
+    pub fn multi_line(&self, indent: &str) -> String {
+        let mut s = String::new();
+        for (i, site) in self.iter().enumerate() {
+            if i > 0 {
+                s.push('\n');
+            }
+            s += &format!("{}{}: {}:{}", indent, i, site.file(), site.line());
+        }
+        if self.omitted > 0 {
+            s += &format!("\n{}... {} more", indent, self.omitted);
+        }
+        s
+    }
+}
+
+
+/// Formats the trace as one line, see `CodeTrace::one_line()`
+impl fmt::Display for CodeTrace {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, site) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "{}:{}", site.file(), site.line())?;
+        }
+        if self.omitted > 0 {
+            write!(f, " -> ... {} more", self.omitted)?;
+        }
+        Ok(())
+    }
+}
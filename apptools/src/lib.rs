@@ -1,10 +1,136 @@
 
+// Allows code generated by `#[derive(AppErrorKind)]` to refer to `::apptools` inside this crate
+extern crate self as apptools;
+
 pub mod err;
 
+#[cfg(feature = "derive")]
+pub use apptools_derive::AppErrorKind;
+
+// Re-exported for the code generated by `declare_app_errors!`
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub use serde;
+
+// Re-exported for `succ_log!` and `log_app_err!`
+#[cfg(feature = "log")]
+#[doc(hidden)]
+pub use log;
+
 
 
 #[cfg(test)]
 mod tests {
 
+    use std::convert::TryFrom;
+
+    use crate::err::{AppErr, AppResult, ErrKind, ErrCategory, ErrSeverity, UnknownErrCode, USER_ERR_CODE_BASE};
+    use crate::{app_err, succ, assert_app_err, assert_app_err_msg_contains, assert_err_origin};
+
+    crate::declare_app_errors!(TestErr, TestErrTable,
+        UserHasNoProfile, "user has no profile",
+        RequestLimitExceeded = 1100, "too many requests"
+            => { category: Resource, severity: Warning, retryable: true },
+        AuthServerBusy, "auth server is busy" => { retryable: true },
+        TokenRevoked, "token was revoked" => { category: Security }
+    );
+
+    type TestResult<T> = AppResult<T, TestErr>;
+
+    #[test]
+    fn codes_are_stable() {
+        assert_eq!(TestErr::NotFound.to_code(), 0);
+        assert_eq!(TestErr::UnexpectedEof.to_code(), 17);
+        assert_eq!(TestErr::FromOtherError.to_code(), 18);
+        assert_eq!(TestErr::Panicked.to_code(), 19);
+        assert_eq!(TestErr::UserHasNoProfile.to_code(), USER_ERR_CODE_BASE);
+        assert_eq!(TestErr::RequestLimitExceeded.to_code(), 1100);
+        assert_eq!(TestErr::AuthServerBusy.to_code(), 1101);
+        assert_eq!(TestErr::TokenRevoked.to_code(), 1102);
+
+        for kind in TestErr::ALL {
+            assert_eq!(TestErr::from_code(kind.to_code()), Some(*kind));
+            assert_eq!(TestErr::try_from(kind.to_code()), Ok(*kind));
+            assert_eq!(TestErr::from_name(kind.name()), Some(*kind));
+        }
+        assert_eq!(TestErr::try_from(1001), Err(UnknownErrCode(1001)));
+        assert_eq!(TestErr::from_name("NoSuchKind"), None);
+    }
+
+    #[test]
+    fn desc_table_matches_enum() {
+        assert_eq!(TestErrTable.len(), TestErr::ALL.len());
+        for (kind, desc) in TestErr::ALL.iter().zip(TestErrTable) {
+            assert_eq!(kind.desc(), *desc);
+        }
+        assert_eq!(TestErr::TokenRevoked.desc(), "token was revoked");
+    }
+
+    #[test]
+    fn metadata_and_defaults() {
+        assert_eq!(TestErr::RequestLimitExceeded.category(), ErrCategory::Resource);
+        assert_eq!(TestErr::RequestLimitExceeded.severity(), ErrSeverity::Warning);
+        assert!(TestErr::RequestLimitExceeded.is_retryable());
+
+        assert_eq!(TestErr::AuthServerBusy.category(), ErrCategory::General);
+        assert_eq!(TestErr::AuthServerBusy.severity(), ErrSeverity::Error);
+        assert!(TestErr::AuthServerBusy.is_retryable());
+
+        assert_eq!(TestErr::TokenRevoked.category(), ErrCategory::Security);
+        assert!(!TestErr::UserHasNoProfile.is_retryable());
+
+        assert_eq!(TestErr::ConnectionReset.category(), ErrCategory::Network);
+        assert_eq!(TestErr::Panicked.severity(), ErrSeverity::Critical);
+    }
+
+    #[test]
+    fn io_kinds_translation() {
+        use std::io::ErrorKind;
+
+        assert_eq!(TestErr::from_io_kind(ErrorKind::NotFound), TestErr::NotFound);
+        assert_eq!(TestErr::from_io_kind(ErrorKind::ConnectionReset), TestErr::ConnectionReset);
+        assert_eq!(TestErr::from_io_kind(ErrorKind::Unsupported), TestErr::Other);
+        assert_eq!(TestErr::from_other_error(), TestErr::FromOtherError);
+        assert_eq!(TestErr::panicked(), TestErr::Panicked);
+    }
+
+    fn find_profile(user: &str) -> TestResult<u32> {
+        if user.is_empty() {
+            return Err(app_err!(TestErr::UserHasNoProfile, Some(format!("user '{}'", user))));
+        }
+        Ok(user.parse::<u32>()?)
+    }
+
+    fn check_user(user: &str) -> TestResult<u32> {
+        let id = succ!(find_profile(user));
+        Ok(id)
+    }
+
+    #[test]
+    fn errors_of_declared_kinds() {
+        // `app_err!` in `find_profile()`, passing through `succ!` in `check_user()` keeps it the origin
+        let origin_line = find_profile("").unwrap_err().origin().unwrap().line();
+
+        let e = assert_app_err!(check_user(""), TestErr::UserHasNoProfile);
+        assert_eq!(e.at.len(), 2);
+        assert_app_err_msg_contains!(check_user(""), "user ''");
+        assert_err_origin!(check_user(""), file!(), origin_line);
+
+        let e = assert_app_err!(check_user("x"), TestErr::InvalidData);
+        assert_eq!(e.to_string(), format!("InvalidData at {}: {}", e.at, TestErr::InvalidData.desc()));
+        assert_eq!(e.root_cause().to_string(), "invalid digit found in string");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected error UserHasNoProfile, got InvalidData")]
+    fn assert_app_err_reports_other_kind() {
+        assert_app_err!(check_user("x"), TestErr::UserHasNoProfile);
+    }
 
+    #[test]
+    #[should_panic(expected = "expected error created at lib.rs:1")]
+    fn assert_err_origin_reports_other_location() {
+        let e: AppErr<TestErr> = app_err!(TestErr::TokenRevoked, None);
+        assert_err_origin!(Err::<(), _>(e), "lib.rs", 1);
+    }
 }